@@ -0,0 +1,147 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    cli::{FetcherFunction, Opts},
+    fetch_output, instances, Output,
+};
+
+/// A URL to generate a fetcher call for
+///
+/// The fields mirror the command line options of the same names,
+/// every other option keeps its default.
+pub struct NurlRequest {
+    /// URL to the repository to be fetched
+    pub url: String,
+    /// The revision or reference to be fetched, the latest revision if unspecified
+    pub rev: Option<String>,
+    /// The fetcher function, inferred from the URL if unspecified
+    pub fetcher: Option<FetcherFunction>,
+    /// Whether to fetch submodules, uses the fetcher's default if unspecified
+    pub submodules: Option<bool>,
+    /// Resolve the revision to its current commit if it refers to a branch
+    pub pin: bool,
+    /// Read and write the prefetch cache
+    pub cache: bool,
+    /// Additional arguments to pass to the fetcher, as Nix expressions
+    pub args: Vec<(String, String)>,
+    /// Additional arguments to pass to the fetcher, as strings
+    pub args_str: Vec<(String, String)>,
+    /// Additional arguments to pass to the fetcher, as JSON values
+    pub args_json: Vec<(String, Value)>,
+    /// Arguments to overwrite in the output, as Nix expressions
+    pub overwrites: Vec<(String, String)>,
+    /// Arguments to overwrite in the output, as strings
+    pub overwrites_str: Vec<(String, String)>,
+    /// Path to nixpkgs (in nix)
+    pub nixpkgs: String,
+    /// Extra indentation (in number of spaces) of the Nix expression
+    pub indent: usize,
+}
+
+/// A fetcher call in the format of --json
+#[derive(Debug, Deserialize)]
+pub struct NurlOutput {
+    /// The fetcher function, e.g. fetchFromGitHub
+    pub fetcher: String,
+    /// The arguments of the fetcher, Nix expressions are objects with a type of nix and a value
+    pub args: Map<String, Value>,
+}
+
+impl NurlRequest {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            rev: None,
+            fetcher: None,
+            submodules: None,
+            pin: false,
+            cache: true,
+            args: Vec::new(),
+            args_str: Vec::new(),
+            args_json: Vec::new(),
+            overwrites: Vec::new(),
+            overwrites_str: Vec::new(),
+            nixpkgs: "<nixpkgs>".into(),
+            indent: 0,
+        }
+    }
+
+    /// Generate the fetcher call as a Nix expression
+    pub fn fetch_nix(&self) -> Result<String> {
+        self.fetch_text(|_| {})
+    }
+
+    /// Only fetch the hash
+    pub fn fetch_hash(&self) -> Result<String> {
+        self.fetch_text(|opts| opts.hash = true)
+    }
+
+    /// Generate the fetcher call in the format of --json
+    pub fn fetch_json(&self) -> Result<NurlOutput> {
+        self.fetch_value(|opts| opts.json = true)
+    }
+
+    /// Parse the URL without fetching the hash
+    pub fn parse(&self) -> Result<NurlOutput> {
+        self.fetch_value(|opts| opts.parse = true)
+    }
+
+    fn fetch_text(&self, mode: impl FnOnce(&mut Opts)) -> Result<String> {
+        match self.fetch(mode)? {
+            Output::Text(text) => Ok(String::from_utf8(text)?),
+            Output::Json(json) => Ok(json.to_string()),
+        }
+    }
+
+    fn fetch_value(&self, mode: impl FnOnce(&mut Opts)) -> Result<NurlOutput> {
+        match self.fetch(mode)? {
+            Output::Json(json) => Ok(serde_json::from_value(json)?),
+            Output::Text(_) => bail!("expected a fetcher call"),
+        }
+    }
+
+    fn fetch(&self, mode: impl FnOnce(&mut Opts)) -> Result<Output> {
+        // every other option keeps the default of the command line
+        let mut opts = Opts::try_parse_from(["nurl", "--", &self.url])?;
+        opts.rev.clone_from(&self.rev);
+        opts.pin = self.pin;
+        opts.no_cache = !self.cache;
+        opts.submodules = self.submodules;
+        opts.fetcher.clone_from(&self.fetcher);
+        opts.nixpkgs.clone_from(&self.nixpkgs);
+        opts.indent = self.indent;
+        opts.args = flatten(&self.args);
+        opts.args_str = flatten(&self.args_str);
+        opts.args_json = self
+            .args_json
+            .iter()
+            .flat_map(|(key, value)| [key.clone(), value.to_string()])
+            .collect();
+        opts.overwrites = flatten(&self.overwrites);
+        opts.overwrites_str = flatten(&self.overwrites_str);
+        mode(&mut opts);
+
+        instances::load_config()?;
+        fetch_output(&opts, &self.url, self.rev.as_deref())
+    }
+}
+
+impl NurlOutput {
+    /// The hash of the source, regardless of whether the fetcher names it hash or sha256
+    pub fn hash(&self) -> Option<&str> {
+        self.args
+            .get("hash")
+            .or_else(|| self.args.get("sha256"))
+            .and_then(Value::as_str)
+    }
+}
+
+fn flatten(pairs: &[(String, String)]) -> Vec<String> {
+    pairs
+        .iter()
+        .flat_map(|(key, value)| [key.clone(), value.clone()])
+        .collect()
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+    process::{Command, ExitStatus, Output, Stdio},
+    sync::{Mutex, OnceLock},
+    thread,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use ureq::{Middleware, MiddlewareNext, Request, Response};
+
+use crate::{sbom::timestamp, warn};
+
+static LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+pub fn open(path: &Path) -> Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let _ = LOG.set(Mutex::new(file));
+    Ok(())
+}
+
+fn record(start: Instant, mut entry: Value) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+
+    entry["time"] = timestamp().unwrap_or_default().into();
+    entry["duration_ms"] = (start.elapsed().as_millis() as u64).into();
+
+    let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = writeln!(log, "{entry}") {
+        warn!("failed to write to the audit log: {e}");
+    }
+}
+
+pub trait AuditedCommand {
+    fn audited_output(&mut self) -> io::Result<Output>;
+    fn audited_output_streaming(&mut self, on_stderr: impl FnMut(&[u8])) -> io::Result<Output>;
+    fn audited_status(&mut self) -> io::Result<ExitStatus>;
+}
+
+impl AuditedCommand for Command {
+    fn audited_output(&mut self) -> io::Result<Output> {
+        let start = Instant::now();
+        let output = self.output();
+        record_command(
+            self,
+            start,
+            output
+                .as_ref()
+                .map(|output| (output.status, Some(output.stdout.len()))),
+        );
+        output
+    }
+
+    // like audited_output, but every line of stderr is passed on as soon as it is written,
+    // so the progress of long running commands is not held back until they exit
+    fn audited_output_streaming(&mut self, mut on_stderr: impl FnMut(&[u8])) -> io::Result<Output> {
+        let start = Instant::now();
+        let output = self
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                // read concurrently, the child blocks when either pipe is full
+                let mut stdout = child.stdout.take().expect("stdout is piped");
+                let reader = thread::spawn(move || {
+                    let mut buf = Vec::new();
+                    stdout.read_to_end(&mut buf).map(|_| buf)
+                });
+
+                let mut stderr = Vec::new();
+                let mut lines = BufReader::new(child.stderr.take().expect("stderr is piped"));
+                loop {
+                    let start = stderr.len();
+                    if lines.read_until(b'\n', &mut stderr)? == 0 {
+                        break;
+                    }
+                    on_stderr(&stderr[start ..]);
+                }
+
+                let status = child.wait()?;
+                let stdout = reader
+                    .join()
+                    .map_err(|_| io::Error::other("failed to read stdout"))??;
+                Ok(Output {
+                    status,
+                    stdout,
+                    stderr,
+                })
+            });
+        record_command(
+            self,
+            start,
+            output
+                .as_ref()
+                .map(|output| (output.status, Some(output.stdout.len()))),
+        );
+        output
+    }
+
+    fn audited_status(&mut self) -> io::Result<ExitStatus> {
+        let start = Instant::now();
+        let status = self.status();
+        record_command(self, start, status.as_ref().map(|status| (*status, None)));
+        status
+    }
+}
+
+fn record_command(
+    cmd: &Command,
+    start: Instant,
+    result: Result<(ExitStatus, Option<usize>), &io::Error>,
+) {
+    if LOG.get().is_none() {
+        return;
+    }
+
+    let args: Vec<_> = cmd
+        .get_args()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+
+    let mut entry = json!({
+        "type": "command",
+        "program": cmd.get_program().to_string_lossy(),
+        "args": args,
+    });
+    match result {
+        Ok((status, bytes)) => {
+            entry["status"] = status.code().into();
+            if let Some(bytes) = bytes {
+                entry["bytes"] = bytes.into();
+            }
+        }
+        Err(e) => entry["error"] = e.to_string().into(),
+    }
+
+    record(start, entry);
+}
+
+pub struct AuditMiddleware;
+
+impl Middleware for AuditMiddleware {
+    fn handle(&self, request: Request, next: MiddlewareNext) -> Result<Response, ureq::Error> {
+        if LOG.get().is_none() {
+            return next.handle(request);
+        }
+
+        let start = Instant::now();
+        let mut entry = json!({
+            "type": "http",
+            "method": request.method(),
+            "url": request.url(),
+        });
+
+        let resp = next.handle(request);
+        match &resp {
+            Ok(resp) | Err(ureq::Error::Status(_, resp)) => {
+                entry["status"] = resp.status().into();
+                // the body is read by the caller, so only the announced length is known here
+                if let Some(bytes) = resp
+                    .header("content-length")
+                    .and_then(|len| len.parse::<u64>().ok())
+                {
+                    entry["bytes"] = bytes.into();
+                }
+            }
+            Err(e) => entry["error"] = e.to_string().into(),
+        }
+
+        record(start, entry);
+        resp
+    }
+}
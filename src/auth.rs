@@ -0,0 +1,48 @@
+use std::{env, fs, path::PathBuf, sync::OnceLock};
+
+static TOKEN: OnceLock<(String, String)> = OnceLock::new();
+
+pub fn set_token(host: String, token: String) {
+    let _ = TOKEN.set((host, token));
+}
+
+// --token takes precedence over the environment variable of the forge, then netrc,
+// it is only sent to the host it was given for
+pub fn token(host: &str, var: &str) -> Option<String> {
+    TOKEN
+        .get()
+        .filter(|(token_host, _)| token_host == host)
+        .map(|(_, token)| token.clone())
+        .or_else(|| env::var(var).ok().filter(|token| !token.is_empty()))
+        .or_else(|| netrc_password(host))
+}
+
+fn netrc_password(host: &str) -> Option<String> {
+    let path = match env::var_os("NETRC") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(env::var_os("HOME")?).join(".netrc"),
+    };
+    let netrc = fs::read_to_string(path).ok()?;
+
+    // the default entry is ignored, since it would send the password to every forge
+    let mut words = netrc.split_whitespace();
+    let mut matched = false;
+    while let Some(word) = words.next() {
+        match word {
+            "machine" => matched = words.next() == Some(host),
+            "default" => matched = false,
+            "password" => {
+                let password = words.next()?;
+                if matched {
+                    return Some(password.into());
+                }
+            }
+            "login" | "account" => {
+                words.next();
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
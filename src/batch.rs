@@ -0,0 +1,132 @@
+use std::{
+    io::{stdin, Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    cli::{FlakeFormat, Opts},
+    fetch_url, info,
+};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Url(String),
+    Object { url: String, rev: Option<String> },
+}
+
+pub fn batch(out: &mut impl Write, opts: &Opts) -> Result<()> {
+    let mut input = String::new();
+    stdin()
+        .read_to_string(&mut input)
+        .context("failed to read stdin")?;
+    let entries = parse_entries(&input)?;
+
+    let jobs = opts
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, Into::into)
+        .min(entries.len());
+    info!("prefetching {} URLs with {jobs} jobs", entries.len());
+
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    let mut failed = 0;
+
+    thread::scope(|scope| -> Result<()> {
+        for _ in 0 .. jobs {
+            let tx = tx.clone();
+            let next = &next;
+            let entries = &entries;
+            scope.spawn(move || {
+                while let Some((url, rev)) = entries.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let mut buf = Vec::new();
+                    let res = fetch_url(&mut buf, opts, url, rev.as_deref());
+                    if tx.send((url, rev, res.map(|_| buf))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // results are written as they finish, so they are keyed by the input instead of ordered
+        for (url, rev, res) in rx {
+            let mut line = json!({ "url": url });
+            if let Some(rev) = rev {
+                line["rev"] = json!(rev);
+            }
+            match res {
+                Ok(buf) => line["result"] = output_value(opts, buf)?,
+                Err(e) => {
+                    failed += 1;
+                    line["error"] = json!(format!("{e:#}"));
+                }
+            }
+
+            serde_json::to_writer(&mut *out, &line)?;
+            writeln!(out)?;
+            out.flush()?;
+        }
+
+        Ok(())
+    })?;
+
+    if failed != 0 {
+        bail!("failed to fetch {failed} of {} URLs", entries.len());
+    }
+
+    Ok(())
+}
+
+fn parse_entries(input: &str) -> Result<Vec<(String, Option<String>)>> {
+    if input.trim_start().starts_with('[') {
+        let entries: Vec<Entry> =
+            serde_json::from_str(input).context("failed to parse the JSON array from stdin")?;
+        return Ok(entries
+            .into_iter()
+            .map(|entry| match entry {
+                Entry::Url(url) => (url, None),
+                Entry::Object { url, rev } => (url, rev),
+            })
+            .collect());
+    }
+
+    let mut entries = Vec::new();
+    for line in input.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let (Some(url), rev, None) = (words.next(), words.next(), words.next()) else {
+            bail!("expected a URL and an optional revision, got {line}");
+        };
+        entries.push((url.into(), rev.map(Into::into)));
+    }
+
+    Ok(entries)
+}
+
+// JSON output, e.g. from --json or --flake lock, is embedded as is, everything else as a string
+fn output_value(opts: &Opts, buf: Vec<u8>) -> Result<Value> {
+    let json = opts.json
+        || opts.parse
+        || opts.sbom.is_some()
+        || matches!(opts.flake, Some(FlakeFormat::Lock));
+
+    Ok(if json {
+        serde_json::from_slice(&buf)?
+    } else {
+        json!(String::from_utf8(buf)?)
+    })
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File},
+    io,
+    os::fd::AsRawFd,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::warn;
+
+// --refresh skips reading the cache, fresh hashes are still written back
+static REFRESH: AtomicBool = AtomicBool::new(false);
+
+pub fn refresh() {
+    REFRESH.store(true, Ordering::Relaxed);
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Entry {
+    pub values: Vec<String>,
+    pub rev: String,
+    pub args_str: Vec<(String, String)>,
+    pub hash: String,
+}
+
+fn cache_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(dir.join("nurl").join("prefetch.json"))
+}
+
+fn read_entries() -> BTreeMap<String, Entry> {
+    cache_path()
+        .and_then(|path| fs::read(path).ok())
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+pub fn get(key: &str) -> Option<Entry> {
+    if REFRESH.load(Ordering::Relaxed) {
+        return None;
+    }
+    read_entries().remove(key)
+}
+
+pub fn insert(key: String, entry: Entry) {
+    let write = || -> Result<()> {
+        let path = cache_path().context("neither XDG_CACHE_HOME nor HOME is set")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // the entries are read and written back as a whole, by threads of --batch and by other
+        // nurl processes, the lock is released when the file is closed
+        let lock_path = path.with_extension("json.lock");
+        let lock = File::create(&lock_path)
+            .with_context(|| format!("failed to open {}", lock_path.display()))?;
+        // SAFETY: the file descriptor stays open until the lock is dropped
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("failed to lock {}", lock_path.display()));
+        }
+
+        let mut entries = read_entries();
+        entries.insert(key, entry);
+
+        let tmp = path.with_extension(format!("json.{}.tmp", process::id()));
+        fs::write(&tmp, serde_json::to_vec(&entries)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("failed to write {}", path.display()))
+    };
+
+    if let Err(e) = write() {
+        warn!("failed to update the prefetch cache: {e:#}");
+    }
+}
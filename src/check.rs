@@ -0,0 +1,36 @@
+use std::io::Write;
+
+use anyhow::Result;
+use serde_json::json;
+
+use crate::{cli::Opts, info, watch::latest_rev};
+
+// returns whether the current revision is outdated
+pub fn check(out: &mut impl Write, opts: &Opts, url: &str, current: &str) -> Result<bool> {
+    let latest = latest_rev(opts, url)?;
+    let outdated = !same_rev(current, &latest);
+    if !outdated {
+        info!("{url} is up to date at {latest}");
+    }
+
+    serde_json::to_writer(
+        &mut *out,
+        &json!({
+            "current": current,
+            "latest": latest,
+            "outdated": outdated,
+        }),
+    )?;
+    writeln!(out)?;
+
+    Ok(outdated)
+}
+
+// abbreviated commit hashes are also accepted as the current revision
+fn same_rev(current: &str, latest: &str) -> bool {
+    let current = current.strip_prefix("refs/tags/").unwrap_or(current);
+    current == latest
+        || current.len() >= 7
+            && current.bytes().all(|c| c.is_ascii_hexdigit())
+            && latest.starts_with(&current.to_ascii_lowercase())
+}
@@ -1,9 +1,11 @@
-use clap::{Parser, ValueEnum};
+use std::{num::NonZeroUsize, path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 /// Generate Nix fetcher calls from repository URLs
 /// https://github.com/nix-community/nurl
 #[derive(Parser)]
-#[command(version, verbatim_doc_comment)]
+#[command(version, verbatim_doc_comment, subcommand_negates_reqs = true)]
 pub struct Opts {
     /// URL to the repository to be fetched
     #[arg(
@@ -16,10 +18,109 @@ pub struct Opts {
     /// The revision or reference to be fetched
     pub rev: Option<String>,
 
-    /// Fetch submodules instead of using the fetcher's default
+    /// The branch containing the revision, only used by builtins.fetchGit
+    ///
+    /// When unspecified, nurl looks for a branch pointing at the revision
+    #[arg(short, long)]
+    pub branch: Option<String>,
+
+    /// Resolve the revision to its current commit if it refers to a branch
+    #[arg(short = 'P', long)]
+    pub pin: bool,
+
+    /// Fetch the latest release or tag instead of the latest commit
+    /// when the revision is unspecified, pre-releases are skipped
+    #[arg(short, long, conflicts_with = "rev")]
+    pub tag: bool,
+
+    /// Only consider tags matching the glob with --tag, e.g. 'v1.*',
+    /// pre-releases are still skipped, regular expressions are not supported
+    #[arg(long, value_name = "GLOB", requires = "tag")]
+    pub tag_filter: Option<String>,
+
+    /// Only consider tags whose version matches the semver requirement with --tag,
+    /// e.g. '>=1.2, <2', the version is the numbers in the tag with missing ones as zero
+    #[arg(long, value_name = "REQ", requires = "tag")]
+    pub tag_semver: Option<String>,
+
+    /// Write the revision as a template with ${version} instead of the tag,
+    /// the version is extracted from the tag and output in an attribute set with the fetcher call as src
+    ///
+    /// Example: --tag --rev-template 'v${version}'
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["hash", "parse", "sbom", "flake"])]
+    pub rev_template: Option<String>,
+
+    /// Print the Nix expression, flake reference, or URL that would be prefetched
+    /// instead of prefetching it
+    #[arg(long, conflicts_with = "expr")]
+    pub dry_run: bool,
+
+    /// Do not read or write the prefetch cache
+    ///
+    /// Hashes of immutable revisions, e.g. commit hashes, are cached in
+    /// $XDG_CACHE_HOME/nurl so rerunning nurl on them skips all network access
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Ignore cached hashes and prefetch again, updating the prefetch cache
+    #[arg(long, conflicts_with = "no_cache")]
+    pub refresh: bool,
+
+    /// Fetch a private repository, only supported by fetchFromGitHub
+    ///
+    /// GITHUB_TOKEN will be used to access the repository if it is set
+    #[arg(long)]
+    pub private: bool,
+
+    /// Access token for the forge of the URL, overriding GITHUB_TOKEN, GITLAB_TOKEN,
+    /// GITEA_TOKEN, and the password for the host in netrc
+    ///
+    /// The token is only sent to the host of the URL
+    #[arg(long, value_name = "TOKEN")]
+    pub token: Option<String>,
+
+    /// Wait for the GitHub API rate limit to reset instead of failing
+    #[arg(long)]
+    pub wait: bool,
+
+    /// Whether to fetch submodules, uses the fetcher's default if unspecified
     #[arg(short = 'S', long, num_args=0..=1, require_equals = true, default_missing_value = "true")]
     pub submodules: Option<bool>,
 
+    /// Path to include in a sparse checkout, can be specified multiple times,
+    /// only supported by fetchgit, fetchFromGitHub, fetchFromGitLab, and fetchFromGitea
+    #[arg(long, value_name = "PATH")]
+    pub sparse: Vec<String>,
+
+    /// The distribution format to fetch, only used by fetchPypi
+    #[arg(long, value_name = "FORMAT", default_value = "sdist")]
+    pub pypi_format: PypiFormat,
+
+    /// Python tag of the wheel to fetch, e.g. py3 or cp311,
+    /// only used with --pypi-format wheel
+    #[arg(long, value_name = "TAG")]
+    pub pypi_python: Option<String>,
+
+    /// ABI tag of the wheel to fetch, e.g. none or abi3,
+    /// only used with --pypi-format wheel
+    #[arg(long, value_name = "TAG")]
+    pub pypi_abi: Option<String>,
+
+    /// Platform tag of the wheel to fetch, e.g. any or manylinux_2_17_x86_64,
+    /// only used with --pypi-format wheel
+    #[arg(long, value_name = "TAG")]
+    pub pypi_platform: Option<String>,
+
+    /// Always download the source instead of using the checksum published by the registry,
+    /// only used by fetchCrate with unpack = false, fetchHex, and fetchPypi
+    #[arg(long)]
+    pub no_trust_upstream_hash: bool,
+
+    /// Name of the manifest file in the manifest repository,
+    /// only used by fetchRepoProject
+    #[arg(long, value_name = "FILE")]
+    pub manifest_name: Option<String>,
+
     /// Specify the fetcher function instead of inferring from the URL
     #[arg(short, long)]
     pub fetcher: Option<FetcherFunction>,
@@ -28,6 +129,11 @@ pub struct Opts {
     #[arg(short = 'F', long, default_value = "fetchgit")]
     pub fallback: FetcherFunction,
 
+    /// The fetcher to retry with when resolving the revision or prefetching fails,
+    /// can be specified multiple times to try them in order
+    #[arg(long, value_name = "FETCHER")]
+    pub retry_with: Vec<FetcherFunction>,
+
     /// Path to nixpkgs (in nix)
     #[arg(short, long, default_value = "<nixpkgs>")]
     pub nixpkgs: String,
@@ -40,6 +146,21 @@ pub struct Opts {
     #[arg(short = 'H', long, group = "format")]
     pub hash: bool,
 
+    /// The encoding of the hash in the output, prefetched hashes are converted,
+    /// update, lock, and sync always write SRI hashes
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "sri",
+        conflicts_with_all = ["cross_check", "sbom", "flake"]
+    )]
+    pub hash_format: HashFormat,
+
+    /// The attribute to write the hash to instead of the default of the fetcher,
+    /// defaults to sha256 with --hash-format base32 or base16
+    #[arg(long, value_name = "ATTR", conflicts_with_all = ["cross_check", "sbom", "flake"])]
+    pub hash_attr: Option<HashAttr>,
+
     /// Output in json format
     #[arg(short, long, group = "format")]
     pub json: bool,
@@ -50,6 +171,45 @@ pub struct Opts {
     #[arg(short, long, group = "format")]
     pub parse: bool,
 
+    /// Output a software bill of materials describing the fetched source,
+    /// with the Nix hash recorded as nix:hash rather than as a checksum
+    ///
+    /// The Nix hash is computed over the unpacked contents rather than the downloaded file,
+    /// so it is an annotation in SPDX and a property in CycloneDX
+    #[arg(long, value_name = "FORMAT", group = "format")]
+    pub sbom: Option<SbomFormat>,
+
+    /// Output a flake input URL or the locked input in the format of flake.lock,
+    /// only supported by fetchFromGitHub, fetchFromGitLab, fetchFromSourcehut, and fetchgit
+    #[arg(long, value_name = "FORMAT", group = "format")]
+    pub flake: Option<FlakeFormat>,
+
+    /// Also compute the hash of the dependencies of the source, i.e. cargoHash, vendorHash,
+    /// or npmDepsHash, which is output in an attribute set with the fetcher call as src
+    #[arg(long, value_name = "LANG", conflicts_with_all = ["hash", "parse", "sbom", "flake"])]
+    pub lang: Option<Lang>,
+
+    /// Refuse to output the revision unless its tag or commit is signed by a key in the keyring,
+    /// only supported by git repositories
+    ///
+    /// When the revision is unspecified, the signature of the latest commit is verified,
+    /// the verified commit is output in place of the tag or branch
+    #[arg(long, value_name = "KEYRING")]
+    pub verify_signatures: Option<PathBuf>,
+
+    /// Fetch the license, description, and homepage from the forge and write them as meta
+    /// in an attribute set with the fetcher call as src, along with the changelog if the revision is a tag,
+    /// only supported by fetchFromGitHub, fetchFromGitLab, and fetchFromGitea
+    #[arg(long, conflicts_with = "verify")]
+    pub meta: bool,
+
+    /// Build the generated expression to verify that it evaluates and the hash matches
+    ///
+    /// Note that overwrites referring to variables not defined in the output
+    /// will cause the verification to fail
+    #[arg(long, conflicts_with = "format")]
+    pub verify: bool,
+
     /// Additional arguments to pass to the fetcher
     #[arg(short, long = "arg", num_args = 2, value_names = ["NAME", "EXPR"])]
     pub args: Vec<String>,
@@ -58,10 +218,17 @@ pub struct Opts {
     #[arg(short = 'A', long = "arg-str", num_args = 2, value_names = ["NAME", "STRING"])]
     pub args_str: Vec<String>,
 
+    /// Same as --arg, but accepts JSON instead of Nix expressions,
+    /// which is converted to Nix and kept as is in the output of --json
+    ///
+    /// Example: --arg-json sparseCheckout '["dir1", "dir2"]'
+    #[arg(long = "arg-json", num_args = 2, value_names = ["NAME", "JSON"])]
+    pub args_json: Vec<String>,
+
     /// Overwrite arguments in the final output,
     /// not taken into consideration when fetching the hash
     ///
-    /// Note that nurl does not verify any of the overwrites,
+    /// Note that nurl only checks that the overwrites are valid Nix syntax,
     /// for the final output to be valid,
     /// the user should not overwrite anything that would change the hash
     ///
@@ -79,6 +246,69 @@ pub struct Opts {
     #[arg(short = 'O', long = "overwrite-str", num_args = 2, value_names = ["NAME", "STRING"])]
     pub overwrites_str: Vec<String>,
 
+    /// Set a Nix configuration option for the spawned nix commands
+    ///
+    /// Examples:
+    /// {n}  --option extra-substituters https://cache.example.org
+    /// {n}  --option tarball-ttl 86400
+    #[arg(long = "option", num_args = 2, value_names = ["NAME", "VALUE"])]
+    pub options: Vec<String>,
+
+    /// Trust the certificates in a PEM file instead of the system ones,
+    /// also used by the spawned git and nix commands
+    #[arg(long, value_name = "FILE")]
+    pub cacert: Option<PathBuf>,
+
+    /// Skip TLS certificate verification for API calls and git,
+    /// only meant for self-signed internal forges
+    #[arg(long)]
+    pub insecure: bool,
+
+    /// Compare the hash with the hash of a git clone of the same revision,
+    /// failing on mismatch, only supported by fetchers that download archives from forges
+    ///
+    /// Note that export-ignore and export-subst in .gitattributes also cause mismatches
+    #[arg(long, conflicts_with = "parse")]
+    pub cross_check: bool,
+
+    /// Write the fetcher, rev, version, and hash to $GITHUB_OUTPUT
+    /// and emit warnings and errors as GitHub Actions workflow annotations
+    #[arg(long)]
+    pub gha: bool,
+
+    /// Append every spawned command and HTTP request to a file as JSON lines
+    #[arg(long, value_name = "FILE")]
+    pub audit_log: Option<PathBuf>,
+
+    /// The format of progress messages and warnings on stderr,
+    /// json emits one event per line for editors and other wrappers
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "human",
+        conflicts_with = "gha"
+    )]
+    pub log_format: LogFormat,
+
+    /// Read URLs from stdin instead, one per line with an optional revision or as a JSON array,
+    /// and prefetch them in parallel, outputting a JSON line for each of them
+    #[arg(long, group = "command", conflicts_with_all = ["url", "rev", "gha"])]
+    pub batch: bool,
+
+    /// The number of URLs to prefetch at the same time, only used with --batch,
+    /// defaults to the number of CPUs
+    #[arg(long, value_name = "N")]
+    pub jobs: Option<NonZeroUsize>,
+
+    /// Compare the latest revision, or the latest tag with --tag, to --current-rev
+    /// without prefetching, outputting a JSON report and exiting with 3 when it is outdated
+    #[arg(long, requires = "current_rev", conflicts_with_all = ["rev", "batch", "format"])]
+    pub check: bool,
+
+    /// The revision to compare the latest revision to, only used with --check
+    #[arg(long, value_name = "REV", requires = "check")]
+    pub current_rev: Option<String>,
+
     /// Instead of fetching a URL, get the hash of a fixed-output derivation,
     /// implies --hash and ignores all other options
     ///
@@ -103,9 +333,142 @@ pub struct Opts {
         allow_hyphen_values = true
     )]
     pub list_sep: Option<String>,
+
+    #[command(subcommand)]
+    pub subcommand: Option<Command>,
 }
 
-#[derive(Clone, Debug, ValueEnum)]
+#[derive(Subcommand)]
+pub enum Command {
+    /// Update the revisions and hashes of the fetcher calls in a Nix file
+    ///
+    /// Every fetcher call with literal arguments is bumped to the latest revision
+    /// and prefetched again, other calls are skipped with a warning,
+    /// the commits between the old and new revisions are summarized when the forge supports it
+    ///
+    /// A revision interpolating the version, e.g. rev = "v${version}", is bumped to the latest tag
+    /// by rewriting the version binding instead, which is also kept in sync with a literal tag
+    #[command(alias = "bump")]
+    Update(UpdateArgs),
+
+    /// Check whether the fetcher calls in a Nix file are up to date without changing it
+    ///
+    /// Outdated calls are printed as JSON lines,
+    /// the exit code is 0 when everything is up to date and 3 when updates are available
+    Outdated {
+        /// The Nix file to check, or a directory with --recursive
+        path: PathBuf,
+
+        /// Check every Nix file under the directory
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Print every call as a JSON array for Renovate's custom managers,
+        /// with currentValue, currentDigest, newValue, and newDigest
+        #[arg(long)]
+        renovate: bool,
+    },
+
+    /// Periodically check a URL or the fetcher calls in a Nix file for new revisions
+    ///
+    /// Every new revision is printed as a JSON line,
+    /// or passed to the command specified by --exec in the NURL_EVENT environment variable
+    Watch {
+        /// URL to the repository to watch, or a Nix file with fetcher calls
+        target: String,
+
+        /// Time between checks, e.g. 30m, 1h, or 1d
+        #[arg(long, default_value = "1h", value_parser = parse_duration)]
+        interval: Duration,
+
+        /// Run the command with `sh -c` for every new revision instead of printing it
+        #[arg(long, value_name = "COMMAND")]
+        exec: Option<String>,
+    },
+
+    /// Generate fetcher calls from the locked inputs of a flake.lock
+    ///
+    /// Outputs an attribute set of all direct inputs,
+    /// or a single fetcher call if an input is specified
+    FromLock {
+        /// Path to the flake.lock file
+        file: PathBuf,
+
+        /// The input to generate a fetcher call for
+        input: Option<String>,
+    },
+
+    /// Convert a fetchgit, fetchzip, or builtins.fetchGit call to the fetcher of its forge
+    ///
+    /// The hash is fetched again since the contents of the outputs differ
+    Convert {
+        /// The fetcher call to convert
+        ///
+        /// Example: 'fetchzip { url = "https://github.com/nix-community/nurl/archive/v0.3.0.tar.gz"; hash = ""; }'
+        expr: String,
+
+        /// The fetcher to convert to, inferred from the URL if unspecified
+        #[arg(short, long)]
+        to: Option<FetcherFunction>,
+    },
+
+    /// Resolve the sources declared in a manifest and pin them in nurl.lock next to it
+    ///
+    /// The manifest is a Nix attribute set of URLs, or attribute sets with url
+    /// and optionally rev, fetcher, and submodules.
+    /// Locked sources are kept until they change in the manifest
+    Lock {
+        /// Path to the manifest
+        #[arg(default_value = "nurl.nix")]
+        manifest: PathBuf,
+
+        /// Lock every source again, fetching the latest revisions of those without a rev
+        #[arg(short, long)]
+        update: bool,
+    },
+
+    /// Generate sources.nix next to a nurl.lock
+    Sync {
+        /// Path to the nurl.lock file
+        #[arg(default_value = "nurl.lock")]
+        file: PathBuf,
+    },
+}
+
+#[derive(Args)]
+pub struct UpdateArgs {
+    /// The Nix file to update in place, or a directory with --recursive
+    pub path: PathBuf,
+
+    /// Update every Nix file under the directory
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Only update files matching the glob, relative to the directory,
+    /// can be specified multiple times
+    #[arg(long, value_name = "GLOB", requires = "recursive")]
+    pub include: Vec<String>,
+
+    /// Skip files matching the glob, relative to the directory,
+    /// can be specified multiple times
+    #[arg(long, value_name = "GLOB", requires = "recursive")]
+    pub exclude: Vec<String>,
+
+    /// Only update the fetcher calls within the value of the attribute,
+    /// e.g. src or the name of a package, can be specified multiple times
+    #[arg(long, value_name = "NAME")]
+    pub attr: Vec<String>,
+
+    /// Print a diff of the changes instead of writing them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print the changes as JSON in the commit protocol of nixpkgs update scripts
+    #[arg(long, conflicts_with = "dry_run")]
+    pub commit_json: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, ValueEnum)]
 #[clap(rename_all = "camelCase")]
 pub enum FetcherFunction {
     #[clap(name = "builtins.fetchGit")]
@@ -120,7 +483,71 @@ pub enum FetcherFunction {
     FetchFromSourcehut,
     FetchHex,
     FetchPypi,
+    FetchRepoProject,
+    Fetchbzr,
+    Fetchdarcs,
+    Fetchfossil,
     Fetchgit,
     Fetchhg,
     Fetchsvn,
+    Fetchurl,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum PypiFormat {
+    Sdist,
+    Wheel,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum SbomFormat {
+    Spdx,
+    Cyclonedx,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum Lang {
+    Rust,
+    Go,
+    Npm,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum FlakeFormat {
+    Input,
+    Lock,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum HashFormat {
+    Sri,
+    Base32,
+    Base16,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum HashAttr {
+    Sha256,
+    Hash,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (n, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: u64 = n
+        .parse()
+        .map_err(|_| format!("expected a number followed by s, m, h, or d, got {s}"))?;
+    let secs = match unit {
+        "" | "s" => n,
+        "m" => n * 60,
+        "h" => n * 60 * 60,
+        "d" => n * 60 * 60 * 24,
+        _ => return Err(format!("unknown unit {unit}, expected s, m, h, or d")),
+    };
+    Ok(Duration::from_secs(secs))
 }
@@ -0,0 +1,116 @@
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    cli::{FetcherFunction, Opts},
+    fetcher::Fetcher,
+    update::{is_ident, parse_attrs, skip_trivia, Attr},
+    warn, with_fetcher,
+};
+
+const ARCHIVE_SUFFIXES: [&str; 5] = [".tar.gz", ".tar.bz2", ".tar.xz", ".tgz", ".zip"];
+
+pub fn convert(
+    out: &mut impl Write,
+    opts: &Opts,
+    expr: &str,
+    to: Option<FetcherFunction>,
+) -> Result<()> {
+    let start = skip_trivia(expr, 0);
+    let end = expr[start ..]
+        .find(|c: char| !c.is_ascii() || !(is_ident(c as u8) || c == '.'))
+        .map_or(expr.len(), |end| start + end);
+    let function = &expr[start .. end];
+
+    let open = skip_trivia(expr, end);
+    if !expr[open ..].starts_with('{') {
+        bail!("expected a fetcher call with an attribute set, got {expr}");
+    }
+    let (attrs, _) = parse_attrs(expr, open + 1).context("failed to parse the fetcher call")?;
+
+    let get = |name: &str| attrs.iter().find(|attr| attr.name == name);
+    let string = |name: &str| {
+        get(name)
+            .map(|attr| {
+                attr.string()
+                    .with_context(|| format!("{name} is not a string literal"))
+            })
+            .transpose()
+    };
+    let boolean = |name: &str, default| match get(name) {
+        Some(Attr { value: "true", .. }) => Ok(true),
+        Some(Attr { value: "false", .. }) => Ok(false),
+        Some(attr) => bail!("cannot evaluate {} = {}", attr.name, attr.value),
+        None => Ok(default),
+    };
+
+    let url = string("url")?.context("url is not specified")?;
+    let (url, rev, submodules, known) = match function.rsplit('.').next() {
+        Some("fetchGit") => (
+            url.to_owned(),
+            string("rev")?.context("rev is not specified")?.to_owned(),
+            boolean("submodules", false)?,
+            &["name", "ref", "rev", "submodules", "url"][..],
+        ),
+        Some("fetchgit") => (
+            url.to_owned(),
+            string("rev")?.context("rev is not specified")?.to_owned(),
+            boolean("fetchSubmodules", true)?,
+            &["fetchSubmodules", "hash", "name", "rev", "sha256", "url"][..],
+        ),
+        Some("fetchzip") => {
+            let (url, rev) = parse_archive(url)
+                .with_context(|| format!("failed to find the repository and revision of {url}"))?;
+            (url, rev, false, &["hash", "name", "sha256", "url"][..])
+        }
+        _ => {
+            bail!("{function} is not supported, expected fetchgit, fetchzip, or builtins.fetchGit");
+        }
+    };
+
+    for attr in &attrs {
+        if !known.contains(&attr.name) {
+            warn!("dropping {} = {}", attr.name, attr.value);
+        }
+    }
+
+    let url: gix_url::Url = url.as_str().try_into()?;
+    with_fetcher(opts, to, &url, |fetcher, url| {
+        fetcher.fetch_nix(
+            out,
+            url,
+            Some(rev),
+            false,
+            !opts.no_cache,
+            Some(submodules),
+            Vec::new(),
+            Vec::new(),
+            Default::default(),
+            opts.nixpkgs.clone(),
+            " ".repeat(opts.indent),
+        )
+    })
+}
+
+// splits an archive URL of a forge into the URL of the repository and the revision
+fn parse_archive(url: &str) -> Option<(String, String)> {
+    let (repo, rev) = if let Some((repo, rest)) = url.split_once("/-/archive/") {
+        (repo, rest.split('/').next()?)
+    } else {
+        let (repo, rest) = url
+            .split_once("/archive/")
+            .or_else(|| url.split_once("/get/"))?;
+        let rev = ARCHIVE_SUFFIXES
+            .iter()
+            .find_map(|suffix| rest.strip_suffix(suffix))?;
+        (repo, rev)
+    };
+
+    let rev = rev
+        .strip_prefix("refs/tags/")
+        .or_else(|| rev.strip_prefix("refs/heads/"))
+        .unwrap_or(rev);
+
+    Some((repo.into(), rev.into()))
+}
@@ -0,0 +1,33 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::{fetcher::json_forge_url, info, prefetch::git_prefetch};
+
+// compares the hash of a forge archive with the hash of a git clone of the same revision,
+// the hash is the prefetched one that the output is written with
+pub fn cross_check(json: &Value, hash: &str) -> Result<()> {
+    let fetcher = json["fetcher"]
+        .as_str()
+        .context("failed to find the fetcher")?;
+    let args = &json["args"];
+
+    if args["fetchSubmodules"] == true {
+        bail!("--cross-check is not supported with submodules, {fetcher} clones with git already");
+    }
+
+    let url = json_forge_url(json)?
+        .with_context(|| format!("--cross-check is not supported by {fetcher}"))?;
+    let rev = args["rev"]
+        .as_str()
+        .or_else(|| args["tag"].as_str())
+        .context("failed to find the revision")?;
+
+    info!("cross-checking {hash} with a git clone of {url}");
+    let git_hash = git_prefetch(false, &url, rev, false)?;
+    if git_hash != hash {
+        bail!("hash mismatch for {rev}\n  {fetcher}: {hash}\n  git clone: {git_hash}");
+    }
+    info!("the hashes match");
+
+    Ok(())
+}
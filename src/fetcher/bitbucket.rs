@@ -1,18 +1,82 @@
+use anyhow::Result;
+use serde::Deserialize;
+
 use crate::{
-    impl_fetcher,
-    simple::{SimpleFetcher, SimpleUrlFetcher},
+    http, impl_fetcher,
+    prefetch::git_branch_rev,
+    simple::{percent_encode, SimpleFetcher, SimpleUrlFetcher},
 };
 
 pub struct FetchFromBitbucket;
 impl_fetcher!(FetchFromBitbucket);
 
+#[derive(Deserialize)]
+struct Repository {
+    mainbranch: Ref,
+}
+
+#[derive(Deserialize)]
+struct Ref {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Branch {
+    target: Commit,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    hash: String,
+}
+
+impl FetchFromBitbucket {
+    fn branch_rev(&self, owner: &str, repo: &str, branch: &str) -> Result<String> {
+        let Branch {
+            target: Commit { hash },
+        } = http::get(&format!(
+            "https://api.bitbucket.org/2.0/repositories/{owner}/{repo}/refs/branches/{}",
+            percent_encode(branch),
+        ))
+        .call()?
+        .into_json()?;
+
+        Ok(hash)
+    }
+}
+
 impl SimpleFetcher<'_, 2> for FetchFromBitbucket {
     const KEYS: [&'static str; 2] = ["owner", "repo"];
     const NAME: &'static str = "fetchFromBitbucket";
+
+    fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
+        let Repository {
+            mainbranch: Ref { name },
+        } = http::get(&format!(
+            "https://api.bitbucket.org/2.0/repositories/{owner}/{repo}",
+        ))
+        .call()?
+        .into_json()?;
+
+        self.branch_rev(owner, repo, &name)
+    }
+
+    fn fetch_branch_rev(&self, [owner, repo]: &[&str; 2], branch: &str) -> Option<String> {
+        git_branch_rev(&format!("https://bitbucket.org/{owner}/{repo}"), branch)
+    }
+
+    fn compare_url(&self, [owner, repo]: &[&str; 2], old: &str, new: &str) -> Option<String> {
+        Some(format!(
+            "https://bitbucket.org/{owner}/{repo}/branches/compare/{new}%0D{old}"
+        ))
+    }
 }
 
 impl SimpleUrlFetcher<'_, 2> for FetchFromBitbucket {
     fn get_url(&self, [owner, repo]: &[&str; 2], rev: &str) -> String {
-        format!("https://bitbucket.org/{owner}/{repo}/get/{rev}.tar.gz")
+        format!(
+            "https://bitbucket.org/{owner}/{repo}/get/{}.tar.gz",
+            percent_encode(rev)
+        )
     }
 }
@@ -1,56 +1,140 @@
 use std::io::Write;
 
 use anyhow::{bail, Context, Result};
-use rustc_hash::FxHashMap;
-use serde_json::json;
+use indexmap::IndexMap;
+use serde_json::{json, Value};
 
-use crate::{fetcher::Fetcher, Url};
+use crate::{
+    fetcher::{Fetcher, Prefetched},
+    log,
+    prefetch::{git_ls_remote, git_ref, git_tags},
+    simple::{latest_tag, nix_escape, Meta, TagFilter},
+    warn, Url,
+};
 
-pub struct BuiltinsFetchGit;
+pub struct BuiltinsFetchGit<'a>(pub Option<&'a str>);
 
-impl<'a> Fetcher<'a> for BuiltinsFetchGit {
-    fn fetch_nix(
+impl BuiltinsFetchGit<'_> {
+    fn branch_ref(&self) -> Option<String> {
+        self.0.map(|branch| {
+            format!(
+                "refs/heads/{}",
+                branch.strip_prefix("refs/heads/").unwrap_or(branch),
+            )
+        })
+    }
+
+    fn resolve_ref(&self, url: &Url, rev: &str) -> Option<String> {
+        if let branch @ Some(_) = self.branch_ref() {
+            return branch;
+        }
+
+        let refs = git_ls_remote(url.as_str()).ok()?;
+        let mut branch = None;
+        for line in refs.lines() {
+            let Some((sha, name)) = line.split_once('\t') else {
+                continue;
+            };
+            if sha != rev {
+                continue;
+            }
+            if name == "HEAD" {
+                return None;
+            }
+            if branch.is_none() && name.starts_with("refs/heads/") {
+                branch = Some(name.into());
+            }
+        }
+
+        if branch.is_none() {
+            warn!("no branch points at {rev}, pass --branch if it is not on the default branch");
+        }
+
+        branch
+    }
+}
+
+impl<'a> Fetcher<'a> for BuiltinsFetchGit<'_> {
+    fn prefetch(
         &self,
-        out: &mut impl Write,
         url: &'a Url,
         rev: Option<String>,
+        _: bool,
+        _: bool,
+        hash_only: bool,
         submodules: Option<bool>,
-        args: Vec<(String, String)>,
+        _: &[(String, String)],
+        _: &[(String, String)],
         args_str: Vec<(String, String)>,
-        overwrites: FxHashMap<String, String>,
         _: String,
+    ) -> Result<Prefetched> {
+        if hash_only {
+            bail!("builtins.fetchGit does not support hashes");
+        }
+
+        log::event(
+            "fetcher",
+            json!({ "fetcher": "builtins.fetchGit", "url": url.as_str() }),
+        );
+        let rev = rev.context("builtins.fetchGit does not support feching the latest revision")?;
+
+        // nothing is fetched, builtins.fetchGit does not take a hash
+        Ok(Prefetched {
+            values: Vec::new(),
+            rev,
+            args_str,
+            hash: String::new(),
+            submodules: submodules == Some(true),
+        })
+    }
+
+    fn write_prefetched_nix(
+        &self,
+        out: &mut impl Write,
+        url: &'a Url,
+        prefetched: Prefetched,
+        args: Vec<(String, String)>,
+        overwrites: IndexMap<String, String>,
         indent: String,
     ) -> Result<()> {
         let mut overwrites = overwrites;
-        let rev = rev.context("builtins.fetchGit does not support feching the latest revision")?;
+        let rev = prefetched.rev;
         let rev_type = if rev.len() == 40 { "rev" } else { "ref" };
 
         writeln!(out, "builtins.fetchGit {{")?;
 
-        if let Some(url) = overwrites.remove("url") {
+        if let Some(url) = overwrites.shift_remove("url") {
             writeln!(out, "{indent}  {url} = {url};")?;
         } else {
-            writeln!(out, r#"{indent}  url = "{url}";"#)?;
+            writeln!(out, r#"{indent}  url = "{}";"#, nix_escape(url.as_str()))?;
         }
 
-        if let Some(rev) = overwrites.remove(rev_type) {
+        if rev_type == "rev" {
+            if let Some(git_ref) = overwrites.shift_remove("ref") {
+                writeln!(out, "{indent}  ref = {git_ref};")?;
+            } else if let Some(git_ref) = self.resolve_ref(url, &rev) {
+                writeln!(out, r#"{indent}  ref = "{git_ref}";"#)?;
+            }
+        }
+
+        if let Some(rev) = overwrites.shift_remove(rev_type) {
             writeln!(out, "{indent}  {rev_type} = {rev};")?;
         } else {
-            writeln!(out, r#"{indent}  {rev_type} = "{rev}";"#)?;
+            writeln!(out, r#"{indent}  {rev_type} = "{}";"#, nix_escape(&rev))?;
         }
 
-        if let Some(submodules) = overwrites.remove("submodules") {
+        if let Some(submodules) = overwrites.shift_remove("submodules") {
             writeln!(out, "{indent}  submodules = {submodules};")?;
-        } else if matches!(submodules, Some(true)) {
+        } else if prefetched.submodules {
             writeln!(out, "{indent}  submodules = true;")?;
         }
 
         for (key, value) in args {
-            let value = overwrites.remove(&key).unwrap_or(value);
+            let value = overwrites.shift_remove(&key).unwrap_or(value);
             writeln!(out, "{indent}  {key} = {value};")?;
         }
-        for (key, value) in args_str {
-            if let Some(value) = overwrites.remove(&key) {
+        for (key, value) in prefetched.args_str {
+            if let Some(value) = overwrites.shift_remove(&key) {
                 writeln!(out, "{indent}  {key} = {value};")?;
             } else {
                 writeln!(out, r#"{indent}  {key} = "{value}";"#)?;
@@ -66,40 +150,33 @@ impl<'a> Fetcher<'a> for BuiltinsFetchGit {
         Ok(())
     }
 
-    fn fetch_hash(
-        &self,
-        _: &mut impl Write,
-        _: &'a Url,
-        _: Option<String>,
-        _: Option<bool>,
-        _: Vec<(String, String)>,
-        _: Vec<(String, String)>,
-        _: String,
-    ) -> Result<()> {
-        bail!("builtins.fetchGit does not support hashes");
-    }
-
-    fn fetch_json(
+    fn prefetched_json(
         &self,
-        out: &mut impl Write,
         url: &'a Url,
-        rev: Option<String>,
-        submodules: Option<bool>,
+        prefetched: Prefetched,
         args: Vec<(String, String)>,
-        args_str: Vec<(String, String)>,
         overwrites: Vec<(String, String)>,
         overwrites_str: Vec<(String, String)>,
-        _: String,
-    ) -> Result<()> {
-        let rev = rev.context("builtins.fetchGit does not support feching the latest revision")?;
+    ) -> Result<Value> {
+        let rev = prefetched.rev;
         let rev_type = if rev.len() == 40 { "rev" } else { "ref" };
 
+        let git_ref = if rev_type == "rev" {
+            self.resolve_ref(url, &rev)
+        } else {
+            None
+        };
+
         let mut fetcher_args = json!({
             "url": url.to_string(),
             rev_type: rev,
         });
 
-        if matches!(submodules, Some(true)) {
+        if let Some(git_ref) = git_ref {
+            fetcher_args["ref"] = json!(git_ref);
+        }
+
+        if prefetched.submodules {
             fetcher_args["submodules"] = json!(true);
         }
 
@@ -109,7 +186,7 @@ impl<'a> Fetcher<'a> for BuiltinsFetchGit {
                 "value": value,
             });
         }
-        for (key, value) in args_str {
+        for (key, value) in prefetched.args_str {
             fetcher_args[key] = json!(value);
         }
 
@@ -123,32 +200,60 @@ impl<'a> Fetcher<'a> for BuiltinsFetchGit {
             fetcher_args[key] = json!(value);
         }
 
-        serde_json::to_writer(
-            out,
-            &json!({
-                "fetcher": "builtins.fetchGit",
-                "args": fetcher_args,
-            }),
-        )?;
+        Ok(json!({
+            "fetcher": "builtins.fetchGit",
+            "args": fetcher_args,
+        }))
+    }
 
-        Ok(())
+    fn write_known(
+        &self,
+        _: &mut impl Write,
+        _: &'a Url,
+        _: String,
+        _: String,
+        _: Option<bool>,
+        _: String,
+    ) -> Result<()> {
+        bail!("builtins.fetchGit does not support known hashes");
     }
 
-    fn to_json(&'a self, out: &mut impl Write, url: &'a Url, rev: Option<String>) -> Result<()> {
+    fn to_json(&'a self, url: &'a Url, rev: Option<String>) -> Result<Value> {
         let rev = rev.context("builtins.fetchGit does not support feching the latest revision")?;
         let rev_type = if rev.len() == 40 { "rev" } else { "ref" };
 
-        serde_json::to_writer(
-            out,
-            &json!({
-                "fetcher": "builtins.fetchGit",
-                "args": {
-                    "url": url.to_string(),
-                    rev_type: rev,
-                },
-            }),
-        )?;
+        let mut fetcher_args = json!({
+            "url": url.to_string(),
+            rev_type: rev,
+        });
 
-        Ok(())
+        if rev_type == "rev" {
+            if let Some(git_ref) = self.branch_ref() {
+                fetcher_args["ref"] = json!(git_ref);
+            }
+        }
+
+        Ok(json!({
+            "fetcher": "builtins.fetchGit",
+            "args": fetcher_args,
+        }))
+    }
+
+    fn compare(&self, _: &'a Url, _: &str, _: &str) -> (Option<String>, Option<u64>) {
+        (None, None)
+    }
+
+    fn meta(&self, _: &'a Url, _: Option<&str>) -> Meta {
+        Meta::default()
+    }
+
+    fn latest_rev(&self, url: &'a Url) -> Result<String> {
+        let target = self.branch_ref().unwrap_or_else(|| "HEAD".into());
+        git_ref(url.as_str(), &target)
+    }
+
+    fn latest_tag(&self, url: &'a Url, filter: &TagFilter) -> Result<String> {
+        latest_tag(git_tags(url.as_str())?, filter)
+            .with_context(|| format!("no matching tags found for {url}"))
     }
 }
@@ -0,0 +1,27 @@
+use anyhow::Result;
+
+use crate::{
+    impl_fetcher,
+    prefetch::bzr_revno,
+    simple::{SimpleFetcher, SimpleFodFetcher},
+    Url,
+};
+
+pub struct Fetchbzr;
+impl_fetcher!(Fetchbzr);
+
+impl<'a> SimpleFetcher<'a, 1> for Fetchbzr {
+    const HASH_KEY: &'static str = "sha256";
+    const KEYS: [&'static str; 1] = ["url"];
+    const NAME: &'static str = "fetchbzr";
+
+    fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
+        Some([url.as_str().trim_end_matches('/')])
+    }
+
+    fn fetch_rev(&self, [url]: &[&str; 1]) -> Result<String> {
+        bzr_revno(url)
+    }
+}
+
+impl SimpleFodFetcher<'_, 1> for Fetchbzr {}
@@ -1,32 +1,195 @@
+use anyhow::{bail, Context, Result};
+use bstr::ByteSlice;
+use semver::Version;
+use serde::Deserialize;
+
 use crate::{
-    impl_fetcher,
+    http, impl_fetcher, info,
+    prefetch::{sri_from_hex, url_prefetch},
     simple::{SimpleFetcher, SimpleUrlFetcher},
-    Url,
+    warn, Url,
 };
 
-pub struct FetchCrate(pub bool);
+pub struct FetchCrate {
+    pub registry: Registry,
+    pub trust_upstream_hash: bool,
+}
 impl_fetcher!(FetchCrate);
 
-impl SimpleFetcher<'_, 1> for FetchCrate {
+pub enum Registry {
+    CratesIo,
+    LibRs,
+    Custom { index: String, dl: String },
+}
+
+#[derive(Deserialize)]
+struct RegistryConfig {
+    dl: String,
+    #[serde(default, rename = "auth-required")]
+    auth_required: bool,
+}
+
+#[derive(Deserialize)]
+struct IndexEntry {
+    vers: String,
+    cksum: String,
+    yanked: bool,
+}
+
+impl Registry {
+    pub fn custom(url: &gix_url::Url) -> Result<Self> {
+        let url = url.to_bstring();
+        let url = url.to_str()?;
+        let index = url
+            .strip_prefix("sparse+")
+            .unwrap_or(url)
+            .trim_end_matches('/')
+            .rsplit_once('/')
+            .context("expected a crate name after the registry index")?
+            .0;
+
+        let RegistryConfig { dl, auth_required } = http::get(&format!("{index}/config.json"))
+            .call()?
+            .into_json()
+            .with_context(|| format!("failed to read the registry config of {index}"))?;
+
+        if dl.contains('{') {
+            bail!("fetchCrate does not support registries with templated download URLs: {dl}");
+        }
+        if auth_required {
+            warn!("{index} requires authentication, which fetchCrate does not support");
+        }
+
+        Ok(Self::Custom {
+            index: index.into(),
+            dl: dl.trim_end_matches('/').into(),
+        })
+    }
+}
+
+impl FetchCrate {
+    pub fn new(registry: Registry, trust_upstream_hash: bool) -> Self {
+        Self {
+            registry,
+            trust_upstream_hash,
+        }
+    }
+
+    fn index_entries(&self, pname: &str) -> Result<(&str, Vec<IndexEntry>)> {
+        let index = match &self.registry {
+            Registry::Custom { index, .. } => index.as_str(),
+            _ => "https://index.crates.io",
+        };
+
+        // the index is sharded by the bytes of the name, which are only ASCII in valid names
+        let name = pname.to_lowercase();
+        if !name.is_ascii() {
+            bail!("{pname} is not a valid crate name");
+        }
+        let path = match name.len() {
+            1 => format!("1/{name}"),
+            2 => format!("2/{name}"),
+            3 => format!("3/{}/{name}", &name[.. 1]),
+            _ => format!("{}/{}/{name}", &name[.. 2], &name[2 .. 4]),
+        };
+
+        let entries = http::get(&format!("{index}/{path}"))
+            .call()?
+            .into_string()?
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+
+        Ok((index, entries))
+    }
+}
+
+impl<'a> SimpleFetcher<'a, 1> for FetchCrate {
+    const HOST_KEY: &'static str = "registryDl";
     const KEYS: [&'static str; 1] = ["pname"];
     const NAME: &'static str = "fetchCrate";
+    const PATH_FORMAT: Option<&'static str> = Some("/crates/pname");
     const REV_KEY: &'static str = "version";
 
-    fn get_values<'a>(&self, url: &'a Url) -> Option<[&'a str; 1]> {
+    fn host(&self) -> Option<&str> {
+        match &self.registry {
+            Registry::Custom { dl, .. } => Some(dl),
+            _ => None,
+        }
+    }
+
+    fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
         let mut xs = url.path_segments();
-        Some([if self.0 {
-            xs.nth(1)?
-        } else {
-            match xs.next()? {
+        Some([match self.registry {
+            Registry::CratesIo => xs.nth(1)?,
+            Registry::LibRs => match xs.next()? {
                 "crates" | "install" => xs.next()?,
                 pname => pname,
-            }
+            },
+            Registry::Custom { .. } => xs.rfind(|x| !x.is_empty())?,
         }])
     }
+
+    fn immutable_rev(&self, _: &str) -> bool {
+        true
+    }
+
+    fn fetch_rev(&self, [pname]: &[&str; 1]) -> Result<String> {
+        let (index, entries) = self.index_entries(pname)?;
+
+        // the index is ordered by publication, not by version,
+        // prereleases are only picked when there is no stable version
+        entries
+            .into_iter()
+            .filter(|entry| !entry.yanked)
+            .filter_map(|entry| Version::parse(&entry.vers).ok())
+            .max_by(|x, y| (x.pre.is_empty(), x).cmp(&(y.pre.is_empty(), y)))
+            .map(|version| version.to_string())
+            .with_context(|| format!("no versions found for {pname} in {index}"))
+    }
 }
 
 impl SimpleUrlFetcher<'_, 1> for FetchCrate {
+    // the checksum in the index covers the .crate file, which is only fetched as is with
+    // unpack = false
+    fn fetch_upstream_hash(&self, [pname]: &[&str; 1], version: &str) -> Option<String> {
+        if !self.trust_upstream_hash {
+            return None;
+        }
+
+        let (_, entries) = self.index_entries(pname).ok()?;
+        let IndexEntry { cksum, .. } = entries.into_iter().find(|entry| entry.vers == version)?;
+
+        info!("using the checksum of {pname} {version} published in the index");
+        sri_from_hex(&cksum).ok()
+    }
+
+    fn fetch(
+        &self,
+        values: &[&str; 1],
+        version: &str,
+        submodules: bool,
+        args: &[(String, String)],
+        args_str: &[(String, String)],
+        nixpkgs: String,
+    ) -> Result<String> {
+        match (args, args_str) {
+            ([], []) => url_prefetch(self.get_url(values, version), true),
+            ([(key, value)], []) if key == "unpack" && value == "false" => {
+                if let Some(hash) = self.fetch_upstream_hash(values, version) {
+                    return Ok(hash);
+                }
+                url_prefetch(self.get_url(values, version), false)
+            }
+            _ => self.fetch_fod(values, version, submodules, args, args_str, nixpkgs),
+        }
+    }
+
     fn get_url(&self, [pname]: &[&str; 1], version: &str) -> String {
-        format!("https://crates.io/api/v1/crates/{pname}/{version}/download")
+        let dl = match &self.registry {
+            Registry::Custom { dl, .. } => dl.as_str(),
+            _ => "https://crates.io/api/v1/crates",
+        };
+        format!("{dl}/{pname}/{version}/download")
     }
 }
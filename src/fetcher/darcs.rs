@@ -0,0 +1,27 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    impl_fetcher,
+    simple::{SimpleFetcher, SimpleFodFetcher},
+    Url,
+};
+
+pub struct Fetchdarcs;
+impl_fetcher!(Fetchdarcs);
+
+impl<'a> SimpleFetcher<'a, 1> for Fetchdarcs {
+    const HASH_KEY: &'static str = "sha256";
+    const KEYS: [&'static str; 1] = ["url"];
+    const NAME: &'static str = "fetchdarcs";
+
+    fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
+        Some([url.as_str().trim_end_matches('/')])
+    }
+
+    // darcs has no revision identifiers that can be queried remotely, only tags can be fetched
+    fn fetch_rev(&self, _: &[&str; 1]) -> Result<String> {
+        bail!("fetchdarcs requires a tag to be specified");
+    }
+}
+
+impl SimpleFodFetcher<'_, 1> for Fetchdarcs {}
@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    http, impl_fetcher,
+    simple::{SimpleFetcher, SimpleFodFetcher},
+    Url,
+};
+
+pub struct Fetchfossil;
+impl_fetcher!(Fetchfossil);
+
+impl<'a> SimpleFetcher<'a, 1> for Fetchfossil {
+    const HASH_KEY: &'static str = "sha256";
+    const KEYS: [&'static str; 1] = ["url"];
+    const NAME: &'static str = "fetchfossil";
+
+    fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
+        Some([url.as_str().trim_end_matches('/')])
+    }
+
+    fn fetch_rev(&self, [url]: &[&str; 1]) -> Result<String> {
+        latest_checkin(url, None)
+            .with_context(|| format!("failed to fetch the latest check-in from {url}"))
+    }
+
+    fn fetch_branch_rev(&self, [url]: &[&str; 1], branch: &str) -> Option<String> {
+        latest_checkin(url, Some(branch))
+    }
+}
+
+impl SimpleFodFetcher<'_, 1> for Fetchfossil {}
+
+// every fossil server has an rss feed of its timeline, unlike the json api which has to be enabled
+fn latest_checkin(url: &str, tag: Option<&str>) -> Option<String> {
+    let mut req = http::get(&format!("{url}/timeline.rss"))
+        .query("y", "ci")
+        .query("n", "1");
+    if let Some(tag) = tag {
+        req = req.query("tag", tag);
+    }
+
+    let feed = req.call().ok()?.into_string().ok()?;
+    let (_, item) = feed.split_once("<item>")?;
+    let (_, link) = item.split_once("<link>")?;
+    let (link, _) = link.split_once("</link>")?;
+    let (_, hash) = link.rsplit_once("/info/")?;
+
+    hash.bytes()
+        .all(|c| c.is_ascii_hexdigit())
+        .then(|| hash.into())
+}
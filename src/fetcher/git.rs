@@ -1,6 +1,14 @@
 use anyhow::Result;
 
-use crate::{impl_fetcher, prefetch::git_prefetch, simple::SimpleFetcher, GitScheme, Url};
+use crate::{
+    impl_fetcher,
+    prefetch::{
+        git_branch_rev, git_prefetch, git_prefetch_script, git_ref, git_tags, FlakesUnavailable,
+        GitOptions,
+    },
+    simple::SimpleFetcher,
+    GitScheme, Url,
+};
 
 pub struct Fetchgit(pub GitScheme);
 impl_fetcher!(Fetchgit);
@@ -18,6 +26,18 @@ impl<'a> SimpleFetcher<'a, 1> for Fetchgit {
             url.as_str()
         }])
     }
+
+    fn fetch_rev(&self, [url]: &[&str; 1]) -> Result<String> {
+        git_ref(url, "HEAD")
+    }
+
+    fn fetch_branch_rev(&self, [url]: &[&str; 1], branch: &str) -> Option<String> {
+        git_branch_rev(url, branch)
+    }
+
+    fn fetch_tags(&self, [url]: &[&str; 1]) -> Result<Vec<String>> {
+        git_tags(url)
+    }
 }
 
 impl<'a> Fetchgit {
@@ -31,9 +51,15 @@ impl<'a> Fetchgit {
         nixpkgs: String,
     ) -> Result<String> {
         if args.is_empty() && args_str.is_empty() {
-            git_prefetch(matches!(self.0, GitScheme::Yes), url, rev, !submodules)
-        } else {
-            self.fetch_fod(values, rev, submodules, args, args_str, nixpkgs)
+            match git_prefetch(matches!(self.0, GitScheme::Yes), url, rev, submodules) {
+                Err(e) if e.is::<FlakesUnavailable>() => {}
+                hash => return hash,
+            }
+        } else if let Some(options) = GitOptions::from_args(args, args_str) {
+            if let Some(hash) = git_prefetch_script(url, rev, submodules, &options)? {
+                return Ok(hash);
+            }
         }
+        self.fetch_fod(values, rev, submodules, args, args_str, nixpkgs)
     }
 }
@@ -2,41 +2,169 @@ use anyhow::{Context, Result};
 use serde::Deserialize;
 
 use crate::{
-    impl_fetcher,
-    prefetch::{git_prefetch, url_prefetch},
-    simple::SimpleFetcher,
+    auth, http, impl_fetcher,
+    prefetch::{
+        git_branch_rev, git_prefetch, git_prefetch_authenticated, git_prefetch_script,
+        url_prefetch, FlakesUnavailable, GitOptions,
+    },
+    simple::{percent_encode, Meta, SimpleFetcher},
 };
 
-pub struct FetchFromGitea<'a>(pub &'a str);
+pub struct FetchFromGitea<'a> {
+    pub host: &'a str,
+    pub token: Option<String>,
+}
 impl_fetcher!(FetchFromGitea<'a>);
 
+impl<'a> FetchFromGitea<'a> {
+    pub fn new(host: &'a str) -> Self {
+        Self {
+            host,
+            token: auth::token(host, "GITEA_TOKEN"),
+        }
+    }
+
+    fn get(&self, url: &str) -> ureq::Request {
+        let req = http::get(url);
+        if let Some(token) = &self.token {
+            req.set("Authorization", &format!("token {token}"))
+        } else {
+            req
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct Commit {
     sha: String,
 }
 
+#[derive(Deserialize)]
+struct Branch {
+    commit: BranchCommit,
+}
+
+#[derive(Deserialize)]
+struct BranchCommit {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Repo {
+    html_url: String,
+    description: Option<String>,
+    website: Option<String>,
+    #[serde(default)]
+    licenses: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Comparison {
+    total_commits: u64,
+}
+
 impl SimpleFetcher<'_, 2> for FetchFromGitea<'_> {
     const KEYS: [&'static str; 2] = ["owner", "repo"];
     const NAME: &'static str = "fetchFromGitea";
     const SUBMODULES_KEY: Option<&'static str> = Some("fetchSubmodules");
 
     fn host(&self) -> Option<&str> {
-        Some(self.0)
+        Some(self.host)
     }
 
     fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
         let url = format!(
             "https://{}/api/v1/repos/{owner}/{repo}/commits?limit=1&stat=false",
-            self.0,
+            self.host,
         );
 
-        let [Commit { sha }] = ureq::get(&url)
+        let [Commit { sha }] = self
+            .get(&url)
             .call()?
             .into_json::<[_; 1]>()
-            .with_context(|| format!("no commits found for https://{}/{owner}/{repo}", self.0))?;
+            .with_context(|| {
+                format!("no commits found for https://{}/{owner}/{repo}", self.host)
+            })?;
 
         Ok(sha)
     }
+
+    fn fetch_branch_rev(&self, [owner, repo]: &[&str; 2], branch: &str) -> Option<String> {
+        // private repositories are only visible to the api with a token
+        if self.token.is_none() {
+            return git_branch_rev(&format!("https://{}/{owner}/{repo}", self.host), branch);
+        }
+
+        let url = format!(
+            "https://{}/api/v1/repos/{owner}/{repo}/branches/{branch}",
+            self.host,
+        );
+
+        let Branch {
+            commit: BranchCommit { id },
+        } = self.get(&url).call().ok()?.into_json().ok()?;
+
+        Some(id)
+    }
+
+    fn fetch_tags(&self, [owner, repo]: &[&str; 2]) -> Result<Vec<String>> {
+        let url = format!(
+            "https://{}/api/v1/repos/{owner}/{repo}/tags?limit=50",
+            self.host,
+        );
+
+        let tags: Vec<Tag> = http::get_pages(url, |url| Ok(self.get(url).call()?))?;
+        Ok(tags.into_iter().map(|tag| tag.name).collect())
+    }
+
+    fn fetch_meta(&self, [owner, repo]: &[&str; 2]) -> Option<Meta> {
+        let url = format!("https://{}/api/v1/repos/{owner}/{repo}", self.host);
+
+        let Repo {
+            html_url,
+            description,
+            website,
+            licenses,
+        } = self.get(&url).call().ok()?.into_json().ok()?;
+
+        Some(Meta {
+            licenses,
+            description,
+            homepage: website.filter(|url| !url.is_empty()).or(Some(html_url)),
+            changelog: None,
+        })
+    }
+
+    fn changelog_url(&self, [owner, repo]: &[&str; 2], tag: &str) -> Option<String> {
+        Some(format!(
+            "https://{}/{owner}/{repo}/releases/tag/{}",
+            self.host,
+            percent_encode(tag),
+        ))
+    }
+
+    fn compare_url(&self, [owner, repo]: &[&str; 2], old: &str, new: &str) -> Option<String> {
+        Some(format!(
+            "https://{}/{owner}/{repo}/compare/{old}...{new}",
+            self.host
+        ))
+    }
+
+    fn count_commits(&self, [owner, repo]: &[&str; 2], old: &str, new: &str) -> Option<u64> {
+        let url = format!(
+            "https://{}/api/v1/repos/{owner}/{repo}/compare/{old}...{new}",
+            self.host,
+        );
+
+        let Comparison { total_commits } = self.get(&url).call().ok()?.into_json().ok()?;
+
+        Some(total_commits)
+    }
 }
 
 impl FetchFromGitea<'_> {
@@ -50,21 +178,41 @@ impl FetchFromGitea<'_> {
         nixpkgs: String,
     ) -> Result<String> {
         if args.is_empty() && args_str.is_empty() {
-            if submodules {
-                git_prefetch(
-                    true,
-                    &format!("git+https://{}/{owner}/{repo}", self.0),
+            // archives cannot be downloaded with a token, fetch private repositories with git
+            let hash = if let Some(token) = &self.token {
+                git_prefetch_authenticated(
+                    &format!("git+https://{}/{owner}/{repo}", self.host),
                     rev,
-                    true,
+                    submodules,
+                    &format!("Authorization: token {token}"),
                 )
+            } else if !submodules {
+                return url_prefetch(
+                    format!(
+                        "https://{}/{owner}/{repo}/archive/{}.tar.gz",
+                        self.host,
+                        percent_encode(rev),
+                    ),
+                    true,
+                );
             } else {
-                url_prefetch(
-                    format!("https://{}/{owner}/{repo}/archive/{rev}.tar.gz", self.0),
+                git_prefetch(
+                    true,
+                    &format!("git+https://{}/{owner}/{repo}", self.host),
+                    rev,
                     true,
                 )
+            };
+            match hash {
+                Err(e) if e.is::<FlakesUnavailable>() => {}
+                hash => return hash,
+            }
+        } else if let (Some(options), None) = (GitOptions::from_args(args, args_str), &self.token) {
+            let url = format!("https://{}/{owner}/{repo}", self.host);
+            if let Some(hash) = git_prefetch_script(&url, rev, submodules, &options)? {
+                return Ok(hash);
             }
-        } else {
-            self.fetch_fod(values, rev, submodules, args, args_str, nixpkgs)
         }
+        self.fetch_fod(values, rev, submodules, args, args_str, nixpkgs)
     }
 }
@@ -1,19 +1,135 @@
-use anyhow::{Context, Result};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread::sleep,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
 use serde::Deserialize;
 
 use crate::{
-    impl_fetcher,
-    simple::{SimpleFetcher, SimpleGitFetcher},
+    auth, http, impl_fetcher, info,
+    prefetch::git_branch_rev,
+    simple::{percent_encode, Meta, SimpleFetcher, SimpleGitFetcher},
+    warn,
 };
 
-pub struct FetchFromGitHub<'a>(pub Option<&'a str>);
+pub struct FetchFromGitHub<'a> {
+    pub host: Option<&'a str>,
+    pub private: bool,
+    pub token: Option<String>,
+    pub wait: bool,
+    redirected: AtomicBool,
+}
 impl_fetcher!(FetchFromGitHub<'a>);
 
+impl<'a> FetchFromGitHub<'a> {
+    pub fn new(host: Option<&'a str>, private: bool, wait: bool) -> Self {
+        Self {
+            host,
+            private,
+            token: auth::token(host.unwrap_or("github.com"), "GITHUB_TOKEN"),
+            wait,
+            redirected: AtomicBool::new(false),
+        }
+    }
+
+    fn get(&self, url: &str) -> Result<ureq::Response> {
+        loop {
+            let req = http::get(url);
+            let req = if let Some(token) = &self.token {
+                req.set("Authorization", &format!("Bearer {token}"))
+            } else {
+                req
+            };
+
+            let resp = match req.call() {
+                Err(ureq::Error::Status(403 | 429, resp))
+                    if resp.header("x-ratelimit-remaining") == Some("0")
+                        || resp.header("retry-after").is_some() =>
+                {
+                    resp
+                }
+                resp => {
+                    // the old path of a moved repository redirects to the new one
+                    if let Ok(resp) | Err(ureq::Error::Status(_, resp)) = &resp {
+                        if resp.get_url() != url {
+                            self.redirected.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    return Ok(resp?);
+                }
+            };
+
+            let secs = if let Some(secs) = resp.header("retry-after") {
+                secs.parse().unwrap_or(60)
+            } else {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                resp.header("x-ratelimit-reset")
+                    .and_then(|reset| reset.parse::<u64>().ok())
+                    .map_or(60, |reset| reset.saturating_sub(now))
+            };
+
+            if !self.wait {
+                let hint = if self.token.is_some() {
+                    ""
+                } else {
+                    "set GITHUB_TOKEN to raise the limit, or "
+                };
+                warn!(
+                    "GitHub API rate limit exceeded, it resets in {secs} seconds, \
+                    {hint}pass --wait to wait for it",
+                );
+                bail!("GitHub API rate limit exceeded");
+            }
+
+            info!("GitHub API rate limit exceeded, waiting {secs} seconds for it to reset");
+            sleep(Duration::from_secs(secs + 1));
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct Commit {
     sha: String,
 }
 
+#[derive(Deserialize)]
+struct Branch {
+    commit: Commit,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    draft: bool,
+    prerelease: bool,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct License {
+    spdx_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Repo {
+    full_name: String,
+    html_url: String,
+    description: Option<String>,
+    homepage: Option<String>,
+    license: Option<License>,
+}
+
+#[derive(Deserialize)]
+struct Comparison {
+    ahead_by: u64,
+}
+
 impl SimpleFetcher<'_, 2> for FetchFromGitHub<'_> {
     const HOST_KEY: &'static str = "githubBase";
     const KEYS: [&'static str; 2] = ["owner", "repo"];
@@ -21,35 +137,157 @@ impl SimpleFetcher<'_, 2> for FetchFromGitHub<'_> {
     const SUBMODULES_KEY: Option<&'static str> = Some("fetchSubmodules");
 
     fn host(&self) -> Option<&str> {
-        self.0
+        self.host
+    }
+
+    fn private(&self) -> bool {
+        self.private
+    }
+
+    fn redirected(&self) -> bool {
+        self.redirected.load(Ordering::Relaxed)
+    }
+
+    fn resolve_redirect(&self, [owner, repo]: &[&str; 2]) -> Option<[String; 2]> {
+        let host = self.host.unwrap_or("github.com");
+        let url = format!("https://api.{host}/repos/{owner}/{repo}");
+
+        let Repo { full_name, .. } = self.get(&url).ok()?.into_json().ok()?;
+        if full_name.eq_ignore_ascii_case(&format!("{owner}/{repo}")) {
+            return None;
+        }
+
+        let (new_owner, new_repo) = full_name.split_once('/')?;
+        warn!("https://{host}/{owner}/{repo} has moved to https://{host}/{full_name}");
+        Some([new_owner.into(), new_repo.into()])
     }
 
     fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
-        let host = self.0.unwrap_or("github.com");
+        let host = self.host.unwrap_or("github.com");
         let url = format!("https://api.{host}/repos/{owner}/{repo}/commits?per_page=1");
 
-        let [Commit { sha }] = ureq::get(&url)
-            .call()?
+        let [Commit { sha }] = self
+            .get(&url)?
             .into_json::<[_; 1]>()
             .with_context(|| format!("no commits found for https://{host}/{owner}/{repo}"))?;
 
         Ok(sha)
     }
+
+    // releases are preferred, but not every project publishes them
+    fn fetch_tags(&self, [owner, repo]: &[&str; 2]) -> Result<Vec<String>> {
+        let host = self.host.unwrap_or("github.com");
+
+        let url = format!("https://api.{host}/repos/{owner}/{repo}/releases?per_page=100");
+        let releases: Vec<Release> = http::get_pages(url, |url| self.get(url))?;
+        let tags: Vec<_> = releases
+            .into_iter()
+            .filter(|release| !release.draft && !release.prerelease)
+            .map(|release| release.tag_name)
+            .collect();
+        if !tags.is_empty() {
+            return Ok(tags);
+        }
+
+        let url = format!("https://api.{host}/repos/{owner}/{repo}/tags?per_page=100");
+        let tags: Vec<Tag> = http::get_pages(url, |url| self.get(url))?;
+        Ok(tags.into_iter().map(|tag| tag.name).collect())
+    }
+
+    fn fetch_branch_rev(&self, [owner, repo]: &[&str; 2], branch: &str) -> Option<String> {
+        let host = self.host.unwrap_or("github.com");
+        // a single ls-remote spares the rate limit and tells branches apart from tags
+        if !self.private {
+            return git_branch_rev(&format!("https://{host}/{owner}/{repo}"), branch);
+        }
+
+        let url = format!("https://api.{host}/repos/{owner}/{repo}/branches/{branch}");
+
+        let Branch {
+            commit: Commit { sha },
+        } = self.get(&url).ok()?.into_json().ok()?;
+
+        Some(sha)
+    }
+
+    fn fetch_meta(&self, [owner, repo]: &[&str; 2]) -> Option<Meta> {
+        let host = self.host.unwrap_or("github.com");
+        let url = format!("https://api.{host}/repos/{owner}/{repo}");
+
+        let Repo {
+            html_url,
+            description,
+            homepage,
+            license,
+            ..
+        } = self.get(&url).ok()?.into_json().ok()?;
+
+        Some(Meta {
+            licenses: license
+                .and_then(|license| license.spdx_id)
+                .filter(|id| id != "NOASSERTION")
+                .into_iter()
+                .collect(),
+            description,
+            homepage: homepage.filter(|url| !url.is_empty()).or(Some(html_url)),
+            changelog: None,
+        })
+    }
+
+    fn changelog_url(&self, [owner, repo]: &[&str; 2], tag: &str) -> Option<String> {
+        Some(format!(
+            "https://{}/{owner}/{repo}/releases/tag/{}",
+            self.host.unwrap_or("github.com"),
+            percent_encode(tag),
+        ))
+    }
+
+    fn compare_url(&self, [owner, repo]: &[&str; 2], old: &str, new: &str) -> Option<String> {
+        Some(format!(
+            "https://{}/{owner}/{repo}/compare/{old}...{new}",
+            self.host.unwrap_or("github.com"),
+        ))
+    }
+
+    fn count_commits(&self, [owner, repo]: &[&str; 2], old: &str, new: &str) -> Option<u64> {
+        let host = self.host.unwrap_or("github.com");
+        let url = format!("https://api.{host}/repos/{owner}/{repo}/compare/{old}...{new}");
+
+        let Comparison { ahead_by } = self.get(&url).ok()?.into_json().ok()?;
+
+        Some(ahead_by)
+    }
 }
 
 impl<'a> SimpleGitFetcher<'a, 2> for FetchFromGitHub<'a> {
     fn get_flake_ref(&self, [owner, repo]: &[&str; 2], rev: &str) -> String {
-        if let Some(host) = self.0 {
-            format!("github:{owner}/{repo}/{rev}?host={host}")
+        if let Some(host) = self.host {
+            format!(
+                "github:{owner}/{repo}/{}?host={}",
+                percent_encode(rev),
+                percent_encode(host),
+            )
         } else {
-            format!("github:{owner}/{repo}/{rev}")
+            format!("github:{owner}/{repo}/{}", percent_encode(rev))
         }
     }
 
     fn get_repo_url(&self, [owner, repo]: &[&str; 2]) -> String {
         format!(
             "git+https://{}/{owner}/{repo}",
-            self.0.unwrap_or("github.com"),
+            self.host.unwrap_or("github.com"),
         )
     }
+
+    fn get_archive_url(&self, [owner, repo]: &[&str; 2], rev: &str) -> Option<String> {
+        Some(format!(
+            "https://{}/{owner}/{repo}/archive/{}.tar.gz",
+            self.host.unwrap_or("github.com"),
+            percent_encode(rev),
+        ))
+    }
+
+    fn access_token(&self) -> Option<(&str, String)> {
+        Some((self.host.unwrap_or("github.com"), self.token.clone()?))
+    }
 }
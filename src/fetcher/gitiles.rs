@@ -1,5 +1,8 @@
+use anyhow::Result;
+
 use crate::{
     impl_fetcher,
+    prefetch::{git_branch_rev, git_ref},
     simple::{SimpleFetcher, SimpleUrlFetcher},
     Url,
 };
@@ -14,6 +17,14 @@ impl<'a> SimpleFetcher<'a, 1> for FetchFromGitiles {
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
         Some([url.as_str()])
     }
+
+    fn fetch_rev(&self, [url]: &[&str; 1]) -> Result<String> {
+        git_ref(url, "HEAD")
+    }
+
+    fn fetch_branch_rev(&self, [url]: &[&str; 1], branch: &str) -> Option<String> {
+        git_branch_rev(url, branch)
+    }
 }
 
 impl SimpleUrlFetcher<'_, 1> for FetchFromGitiles {
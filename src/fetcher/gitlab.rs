@@ -1,17 +1,19 @@
-use std::{cell::OnceCell, fmt::Write};
+use std::{fmt::Write, sync::OnceLock};
 
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use serde::{de::IgnoredAny, Deserialize};
 
 use crate::{
-    impl_fetcher,
-    simple::{SimpleFetcher, SimpleGitFetcher},
-    Url,
+    auth, http, impl_fetcher,
+    prefetch::git_branch_rev,
+    simple::{percent_encode, Meta, SimpleFetcher, SimpleGitFetcher},
+    warn, Url,
 };
 
 pub struct FetchFromGitLab<'a> {
     pub host: Option<&'a str>,
-    pub group: OnceCell<&'a str>,
+    pub group: OnceLock<&'a str>,
+    pub token: Option<String>,
 }
 impl_fetcher!(FetchFromGitLab<'a>);
 
@@ -19,7 +21,17 @@ impl<'a> FetchFromGitLab<'a> {
     pub fn new(host: Option<&'a str>) -> Self {
         Self {
             host,
-            group: OnceCell::new(),
+            group: OnceLock::new(),
+            token: auth::token(host.unwrap_or("gitlab.com"), "GITLAB_TOKEN"),
+        }
+    }
+
+    fn get(&self, url: &str) -> ureq::Request {
+        let req = http::get(url);
+        if let Some(token) = &self.token {
+            req.set("PRIVATE-TOKEN", token)
+        } else {
+            req
         }
     }
 }
@@ -29,9 +41,39 @@ struct Commit {
     id: String,
 }
 
+#[derive(Deserialize)]
+struct Branch {
+    commit: Commit,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Comparison {
+    commits: Vec<IgnoredAny>,
+}
+
+#[derive(Deserialize)]
+struct License {
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct Project {
+    path_with_namespace: String,
+    web_url: String,
+    description: Option<String>,
+    visibility: Option<String>,
+    license: Option<License>,
+}
+
 impl<'a> SimpleFetcher<'a, 2> for FetchFromGitLab<'a> {
     const KEYS: [&'static str; 2] = ["owner", "repo"];
     const NAME: &'static str = "fetchFromGitLab";
+    const PATH_FORMAT: Option<&'static str> = Some("/[group/]owner/repo");
     const SUBMODULES_KEY: Option<&'static str> = Some("fetchSubmodules");
 
     fn host(&self) -> Option<&str> {
@@ -55,6 +97,52 @@ impl<'a> SimpleFetcher<'a, 2> for FetchFromGitLab<'a> {
         })
     }
 
+    fn resolve_redirect(&self, [owner, repo]: &[&str; 2]) -> Option<[String; 2]> {
+        let host = self.host.unwrap_or("gitlab.com");
+
+        let mut path = String::new();
+        if let Some(group) = self.group.get() {
+            path.push_str(group);
+            path.push('/');
+        }
+        path.push_str(owner);
+        path.push('/');
+        path.push_str(repo);
+
+        let url = format!(
+            "https://{host}/api/v4/projects/{}",
+            path.replace('/', "%2F"),
+        );
+        let Project {
+            path_with_namespace,
+            visibility,
+            ..
+        } = self.get(&url).call().ok()?.into_json().ok()?;
+
+        if let Some(visibility @ ("private" | "internal")) = visibility.as_deref() {
+            warn!(
+                "https://{host}/{path} is {visibility}, building the generated expression \
+                requires credentials for {host}, e.g. with `netrcPhase` or `netrcImpureEnvVars`",
+            );
+        }
+
+        if path_with_namespace.eq_ignore_ascii_case(&path) {
+            return None;
+        }
+
+        warn!("https://{host}/{path} has moved to https://{host}/{path_with_namespace}");
+
+        let mut xs = path_with_namespace.rsplitn(3, '/');
+        let new_repo = xs.next()?;
+        let new_owner = xs.next()?;
+        if xs.next() != self.group.get().copied() {
+            warn!("the group of the project has changed, keeping the original location");
+            return None;
+        }
+
+        Some([new_owner.into(), new_repo.into()])
+    }
+
     fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
         let host = self.host.unwrap_or("gitlab.com");
 
@@ -65,7 +153,8 @@ impl<'a> SimpleFetcher<'a, 2> for FetchFromGitLab<'a> {
         }
         write!(url, "{owner}%2F{repo}/repository/commits?per_page=1")?;
 
-        let [Commit { id }] = ureq::get(&url)
+        let [Commit { id }] = self
+            .get(&url)
             .call()?
             .into_json::<[_; 1]>()
             .with_context(|| {
@@ -82,6 +171,115 @@ impl<'a> SimpleFetcher<'a, 2> for FetchFromGitLab<'a> {
 
         Ok(id)
     }
+
+    fn fetch_branch_rev(&self, values: &[&str; 2], branch: &str) -> Option<String> {
+        // private projects are only visible to the api with a token
+        if self.token.is_none() {
+            let url = self.get_repo_url(values);
+            return git_branch_rev(url.strip_prefix("git+")?, branch);
+        }
+
+        let [owner, repo] = values;
+        let host = self.host.unwrap_or("gitlab.com");
+
+        let mut url = format!("https://{host}/api/v4/projects/");
+        if let Some(group) = self.group.get() {
+            url.push_str(group);
+            url.push_str("%2F");
+        }
+        write!(
+            url,
+            "{owner}%2F{repo}/repository/branches/{}",
+            branch.replace('/', "%2F"),
+        )
+        .ok()?;
+
+        let Branch {
+            commit: Commit { id },
+        } = self.get(&url).call().ok()?.into_json().ok()?;
+
+        Some(id)
+    }
+
+    fn fetch_tags(&self, [owner, repo]: &[&str; 2]) -> Result<Vec<String>> {
+        let host = self.host.unwrap_or("gitlab.com");
+
+        let mut url = format!("https://{host}/api/v4/projects/");
+        if let Some(group) = self.group.get() {
+            url.push_str(group);
+            url.push_str("%2F");
+        }
+        write!(url, "{owner}%2F{repo}/repository/tags?per_page=100")?;
+
+        let tags: Vec<Tag> = http::get_pages(url, |url| Ok(self.get(url).call()?))?;
+        Ok(tags.into_iter().map(|tag| tag.name).collect())
+    }
+
+    fn fetch_meta(&self, [owner, repo]: &[&str; 2]) -> Option<Meta> {
+        let host = self.host.unwrap_or("gitlab.com");
+
+        let mut url = format!("https://{host}/api/v4/projects/");
+        if let Some(group) = self.group.get() {
+            url.push_str(group);
+            url.push_str("%2F");
+        }
+        write!(url, "{owner}%2F{repo}?license=true").ok()?;
+
+        let Project {
+            web_url,
+            description,
+            license,
+            ..
+        } = self.get(&url).call().ok()?.into_json().ok()?;
+
+        Some(Meta {
+            licenses: license.map(|license| license.key).into_iter().collect(),
+            description,
+            homepage: Some(web_url),
+            changelog: None,
+        })
+    }
+
+    fn changelog_url(&self, [owner, repo]: &[&str; 2], tag: &str) -> Option<String> {
+        let mut url = format!("https://{}/", self.host.unwrap_or("gitlab.com"));
+        if let Some(group) = self.group.get() {
+            url.push_str(group);
+            url.push('/');
+        }
+        write!(url, "{owner}/{repo}/-/releases/{}", percent_encode(tag)).ok()?;
+        Some(url)
+    }
+
+    fn compare_url(&self, [owner, repo]: &[&str; 2], old: &str, new: &str) -> Option<String> {
+        let mut url = format!("https://{}/", self.host.unwrap_or("gitlab.com"));
+        if let Some(group) = self.group.get() {
+            url.push_str(group);
+            url.push('/');
+        }
+        write!(url, "{owner}/{repo}/-/compare/{old}...{new}").ok()?;
+        Some(url)
+    }
+
+    fn count_commits(&self, [owner, repo]: &[&str; 2], old: &str, new: &str) -> Option<u64> {
+        let host = self.host.unwrap_or("gitlab.com");
+
+        let mut url = format!("https://{host}/api/v4/projects/");
+        if let Some(group) = self.group.get() {
+            url.push_str(group);
+            url.push_str("%2F");
+        }
+        write!(
+            url,
+            "{owner}%2F{repo}/repository/compare?from={}&to={}",
+            percent_encode(old),
+            percent_encode(new),
+        )
+        .ok()?;
+
+        let Comparison { commits } = self.get(&url).call().ok()?.into_json().ok()?;
+
+        commits.len().try_into().ok()
+    }
 }
 
 impl<'a> SimpleGitFetcher<'a, 2> for FetchFromGitLab<'a> {
@@ -95,10 +293,10 @@ impl<'a> SimpleGitFetcher<'a, 2> for FetchFromGitLab<'a> {
         flake_ref.push('/');
         flake_ref.push_str(repo);
         flake_ref.push('/');
-        flake_ref.push_str(rev);
+        flake_ref.push_str(&percent_encode(rev));
         if let Some(host) = self.host {
             flake_ref.push_str("?host=");
-            flake_ref.push_str(host);
+            flake_ref.push_str(&percent_encode(host));
         }
         flake_ref
     }
@@ -116,4 +314,27 @@ impl<'a> SimpleGitFetcher<'a, 2> for FetchFromGitLab<'a> {
         flake_ref.push_str(repo);
         flake_ref
     }
+
+    fn get_archive_url(&self, [owner, repo]: &[&str; 2], rev: &str) -> Option<String> {
+        let mut url = format!(
+            "https://{}/api/v4/projects/",
+            self.host.unwrap_or("gitlab.com"),
+        );
+        if let Some(group) = self.group.get() {
+            url.push_str(group);
+            url.push_str("%2F");
+        }
+        write!(
+            url,
+            "{owner}%2F{repo}/repository/archive.tar.gz?sha={}",
+            percent_encode(rev),
+        )
+        .ok()?;
+        Some(url)
+    }
+
+    fn access_token(&self) -> Option<(&str, String)> {
+        let token = self.token.as_ref()?;
+        Some((self.host.unwrap_or("gitlab.com"), format!("PAT:{token}")))
+    }
 }
@@ -1,27 +1,140 @@
+use std::{env, sync::OnceLock};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
 use crate::{
-    impl_fetcher,
+    http, impl_fetcher, info,
+    prefetch::sri_from_hex,
     simple::{SimpleFetcher, SimpleUrlFetcher},
     Url,
 };
 
-pub struct FetchHex;
-impl_fetcher!(FetchHex);
+pub struct FetchHex<'a> {
+    pub host: Option<&'a str>,
+    pub org: OnceLock<&'a str>,
+    pub repo: OnceLock<&'a str>,
+    pub trust_upstream_hash: bool,
+}
+impl_fetcher!(FetchHex<'a>);
+
+impl<'a> FetchHex<'a> {
+    pub fn new(host: Option<&'a str>, trust_upstream_hash: bool) -> Self {
+        Self {
+            host,
+            org: OnceLock::new(),
+            repo: OnceLock::new(),
+            trust_upstream_hash,
+        }
+    }
+
+    pub fn is_public(&self, url: &Url) -> bool {
+        self.host.is_none() && matches!(org_pkg(url), Some((None, _)))
+    }
+
+    fn get_api(&self, url: &str) -> ureq::Request {
+        let req = http::get(url);
+        if let Ok(key) = env::var("HEX_API_KEY") {
+            req.set("Authorization", &key)
+        } else {
+            req
+        }
+    }
+}
 
-impl<'a> SimpleFetcher<'a, 1> for FetchHex {
+// /packages/<pkg>[/<version>] or /packages/<org>/<pkg>[/<version>],
+// package names start with a letter so a version page is never mistaken for an organization
+fn org_pkg<'a>(url: &'a Url) -> Option<(Option<&'a str>, &'a str)> {
+    let mut xs = url.path_segments().skip(1).filter(|x| !x.is_empty());
+    let x = xs.next()?;
+    match xs.next() {
+        Some(pkg) if !pkg.starts_with(|c: char| c.is_ascii_digit()) => Some((Some(x), pkg)),
+        _ => Some((None, x)),
+    }
+}
+
+#[derive(Deserialize)]
+struct Release {
+    checksum: String,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    latest_stable_version: Option<String>,
+    latest_version: String,
+}
+
+impl<'a> SimpleFetcher<'a, 1> for FetchHex<'a> {
     const HASH_KEY: &'static str = "sha256";
     const KEYS: [&'static str; 1] = ["pkg"];
     const NAME: &'static str = "fetchHex";
+    const PATH_FORMAT: Option<&'static str> = Some("/packages/pkg");
     const REV_KEY: &'static str = "version";
 
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
-        Some([url.path_segments().nth(1)?])
+        if self.host.is_some() {
+            let (repo, pkg) = url.as_str().split_once("/packages/")?;
+            let _ = self.repo.set(repo);
+            return Some([pkg.trim_end_matches('/')]);
+        }
+
+        let (org, pkg) = org_pkg(url)?;
+        if let Some(org) = org {
+            let _ = self.org.set(org);
+        }
+        Some([pkg])
+    }
+
+    fn fetch_rev(&self, [pkg]: &[&str; 1]) -> Result<String> {
+        if self.host.is_some() {
+            bail!("fetchHex does not support fetching the latest version from self-hosted repositories");
+        }
+
+        let url = match self.org.get() {
+            Some(org) => format!("https://hex.pm/api/repos/{org}/packages/{pkg}"),
+            None => format!("https://hex.pm/api/packages/{pkg}"),
+        };
+
+        let Package {
+            latest_stable_version,
+            latest_version,
+        } = self
+            .get_api(&url)
+            .call()?
+            .into_json()
+            .with_context(|| format!("failed to find the latest version of {pkg}"))?;
+
+        Ok(latest_stable_version.unwrap_or(latest_version))
     }
 }
 
-impl SimpleUrlFetcher<'_, 1> for FetchHex {
+impl<'a> SimpleUrlFetcher<'a, 1> for FetchHex<'a> {
     const UNPACK: bool = false;
 
+    fn fetch_upstream_hash(&self, [pkg]: &[&str; 1], version: &str) -> Option<String> {
+        if !self.trust_upstream_hash || self.host.is_some() {
+            return None;
+        }
+
+        let url = match self.org.get() {
+            Some(org) => {
+                format!("https://hex.pm/api/repos/{org}/packages/{pkg}/releases/{version}")
+            }
+            None => format!("https://hex.pm/api/packages/{pkg}/releases/{version}"),
+        };
+        let Release { checksum } = self.get_api(&url).call().ok()?.into_json().ok()?;
+
+        info!("using the checksum of {pkg} {version} published by hex.pm");
+        sri_from_hex(&checksum).ok()
+    }
+
     fn get_url(&self, [pkg]: &[&str; 1], version: &str) -> String {
-        format!("https://repo.hex.pm/tarballs/{pkg}-{version}.tar")
+        if let Some(repo) = self.repo.get() {
+            format!("{repo}/tarballs/{pkg}-{version}.tar")
+        } else if let Some(org) = self.org.get() {
+            format!("https://repo.hex.pm/repos/{org}/tarballs/{pkg}-{version}.tar")
+        } else {
+            format!("https://repo.hex.pm/tarballs/{pkg}-{version}.tar")
+        }
     }
 }
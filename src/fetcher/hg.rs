@@ -1,25 +1,93 @@
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use serde::Deserialize;
+
 use crate::{
-    impl_fetcher,
+    http, impl_fetcher, info,
     simple::{SimpleFetcher, SimpleFlakeFetcher},
     Url,
 };
 
-pub struct Fetchhg(pub bool);
+pub struct Fetchhg {
+    pub hg_scheme: bool,
+    pub rev_comment: OnceLock<String>,
+}
 impl_fetcher!(Fetchhg);
 
+impl Fetchhg {
+    pub fn new(hg_scheme: bool) -> Self {
+        Self {
+            hg_scheme,
+            rev_comment: OnceLock::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Changeset {
+    node: String,
+    branch: String,
+    bookmarks: Vec<String>,
+}
+
 impl<'a> SimpleFetcher<'a, 1> for Fetchhg {
     const HASH_KEY: &'static str = "sha256";
     const KEYS: [&'static str; 1] = ["url"];
     const NAME: &'static str = "fetchhg";
     const SUBMODULES_KEY: Option<&'static str> = Some("fetchSubrepos");
 
+    fn rev_comment(&self) -> Option<&str> {
+        self.rev_comment.get().map(String::as_str)
+    }
+
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
-        Some([if self.0 {
+        Some([if self.hg_scheme {
             url.as_str().strip_prefix("hg+")?
         } else {
             url.as_str()
         }])
     }
+
+    fn fetch_branch_rev(&self, [url]: &[&str; 1], branch: &str) -> Option<String> {
+        if !url.starts_with("http") {
+            return None;
+        }
+
+        let Changeset {
+            node,
+            branch: node_branch,
+            bookmarks,
+        } = http::get(&format!("{}/json-rev/{branch}", url.trim_end_matches('/')))
+            .call()
+            .ok()?
+            .into_json()
+            .ok()?;
+
+        let comment = if bookmarks.iter().any(|bookmark| bookmark == branch) {
+            format!("bookmark {branch}")
+        } else if node_branch == branch {
+            format!("branch {branch}")
+        } else {
+            return None;
+        };
+
+        info!("resolved {comment} to {node}");
+        let _ = self.rev_comment.set(comment);
+        Some(node)
+    }
+
+    fn resolve_rev(&self, values: &[&str; 1], rev: Option<String>, _: bool) -> Result<String> {
+        let Some(rev) = rev else {
+            return self.fetch_rev(values);
+        };
+
+        if rev.len() == 40 && rev.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(rev);
+        }
+
+        Ok(self.fetch_branch_rev(values, &rev).unwrap_or(rev))
+    }
 }
 
 impl SimpleFlakeFetcher<'_, 1> for Fetchhg {
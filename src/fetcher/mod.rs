@@ -1,6 +1,9 @@
 mod bitbucket;
 mod builtin_git;
+mod bzr;
 mod crates_io;
+mod darcs;
+mod fossil;
 mod git;
 mod gitea;
 mod github;
@@ -10,68 +13,174 @@ mod hex;
 mod hg;
 mod pypi;
 mod repo_or_cz;
+mod repo_project;
 mod sourcehut;
 mod svn;
+mod url;
 
 use std::io::Write;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
 use enum_dispatch::enum_dispatch;
-use rustc_hash::FxHashMap;
+use indexmap::IndexMap;
+use serde_json::Value;
 
 pub use self::{
-    bitbucket::FetchFromBitbucket, builtin_git::BuiltinsFetchGit, crates_io::FetchCrate,
-    git::Fetchgit, gitea::FetchFromGitea, github::FetchFromGitHub, gitiles::FetchFromGitiles,
-    gitlab::FetchFromGitLab, hex::FetchHex, hg::Fetchhg, pypi::FetchPypi,
-    repo_or_cz::FetchFromRepoOrCz, sourcehut::FetchFromSourcehut, svn::Fetchsvn,
+    bitbucket::FetchFromBitbucket,
+    builtin_git::BuiltinsFetchGit,
+    bzr::Fetchbzr,
+    crates_io::{FetchCrate, Registry},
+    darcs::Fetchdarcs,
+    fossil::Fetchfossil,
+    git::Fetchgit,
+    gitea::FetchFromGitea,
+    github::FetchFromGitHub,
+    gitiles::FetchFromGitiles,
+    gitlab::FetchFromGitLab,
+    hex::FetchHex,
+    hg::Fetchhg,
+    pypi::{FetchPypi, WheelTags},
+    repo_or_cz::FetchFromRepoOrCz,
+    repo_project::FetchRepoProject,
+    sourcehut::{find_sourcehut_host, FetchFromSourcehut},
+    svn::Fetchsvn,
+    url::Fetchurl,
 };
-use crate::Url;
+use crate::{
+    cli::FetcherFunction,
+    simple::{Meta, TagFilter},
+    Url,
+};
+
+// the result of prefetching a source, every output format is written from it
+// so the source is only fetched once,
+// fetchurl stores its URLs as the values and builtins.fetchGit has no hash
+#[derive(Clone)]
+pub struct Prefetched {
+    pub values: Vec<String>,
+    pub rev: String,
+    pub args_str: Vec<(String, String)>,
+    pub hash: String,
+    pub submodules: bool,
+}
 
 #[enum_dispatch]
 pub trait Fetcher<'a> {
-    fn fetch_nix(
+    fn prefetch(
         &self,
-        out: &mut impl Write,
         url: &'a Url,
         rev: Option<String>,
+        pin: bool,
+        cache: bool,
+        hash_only: bool,
         submodules: Option<bool>,
-        args: Vec<(String, String)>,
+        overwrites: &[(String, String)],
+        args: &[(String, String)],
         args_str: Vec<(String, String)>,
-        overwrites: FxHashMap<String, String>,
         nixpkgs: String,
+    ) -> Result<Prefetched>;
+
+    fn write_prefetched_nix(
+        &self,
+        out: &mut impl Write,
+        url: &'a Url,
+        prefetched: Prefetched,
+        args: Vec<(String, String)>,
+        overwrites: IndexMap<String, String>,
         indent: String,
     ) -> Result<()>;
 
-    fn fetch_hash(
+    fn prefetched_json(
+        &self,
+        url: &'a Url,
+        prefetched: Prefetched,
+        args: Vec<(String, String)>,
+        overwrites: Vec<(String, String)>,
+        overwrites_str: Vec<(String, String)>,
+    ) -> Result<Value>;
+
+    fn fetch_nix(
         &self,
         out: &mut impl Write,
         url: &'a Url,
         rev: Option<String>,
+        pin: bool,
+        cache: bool,
         submodules: Option<bool>,
         args: Vec<(String, String)>,
         args_str: Vec<(String, String)>,
+        overwrites: IndexMap<String, String>,
         nixpkgs: String,
-    ) -> Result<()>;
+        indent: String,
+    ) -> Result<()> {
+        let prefetched = self.prefetch(
+            url,
+            rev,
+            pin,
+            cache,
+            false,
+            submodules,
+            &overwrites.clone().into_iter().collect::<Vec<_>>(),
+            &args,
+            args_str,
+            nixpkgs,
+        )?;
+        self.write_prefetched_nix(out, url, prefetched, args, overwrites, indent)
+    }
 
     fn fetch_json(
         &self,
-        out: &mut impl Write,
         url: &'a Url,
         rev: Option<String>,
+        pin: bool,
+        cache: bool,
         submodules: Option<bool>,
         args: Vec<(String, String)>,
         args_str: Vec<(String, String)>,
         overwrites: Vec<(String, String)>,
         overwrites_str: Vec<(String, String)>,
         nixpkgs: String,
+    ) -> Result<Value> {
+        let prefetched = self.prefetch(
+            url,
+            rev,
+            pin,
+            cache,
+            false,
+            submodules,
+            &overwrites,
+            &args,
+            args_str,
+            nixpkgs,
+        )?;
+        self.prefetched_json(url, prefetched, args, overwrites, overwrites_str)
+    }
+
+    fn write_known(
+        &self,
+        out: &mut impl Write,
+        url: &'a Url,
+        rev: String,
+        hash: String,
+        submodules: Option<bool>,
+        indent: String,
     ) -> Result<()>;
 
-    fn to_json(&'a self, out: &mut impl Write, url: &'a Url, rev: Option<String>) -> Result<()>;
+    fn to_json(&'a self, url: &'a Url, rev: Option<String>) -> Result<Value>;
+
+    fn compare(&self, url: &'a Url, old: &str, new: &str) -> (Option<String>, Option<u64>);
+
+    fn meta(&self, url: &'a Url, tag: Option<&str>) -> Meta;
+
+    fn latest_rev(&self, url: &'a Url) -> Result<String>;
+
+    fn latest_tag(&self, url: &'a Url, filter: &TagFilter) -> Result<String>;
 }
 
 #[enum_dispatch(Fetcher)]
 pub enum FetcherDispatch<'a> {
-    BuiltinsFetchGit(BuiltinsFetchGit),
+    BuiltinsFetchGit(BuiltinsFetchGit<'a>),
     FetchCrate(FetchCrate),
     FetchFromBitbucket(FetchFromBitbucket),
     FetchFromGitHub(FetchFromGitHub<'a>),
@@ -80,125 +189,231 @@ pub enum FetcherDispatch<'a> {
     FetchFromGitiles(FetchFromGitiles),
     FetchFromRepoOrCz(FetchFromRepoOrCz),
     FetchFromSourcehut(FetchFromSourcehut<'a>),
-    FetchHex(FetchHex),
-    FetchPypi(FetchPypi),
+    FetchHex(FetchHex<'a>),
+    FetchPypi(FetchPypi<'a>),
+    FetchRepoProject(FetchRepoProject),
+    Fetchbzr(Fetchbzr),
+    Fetchdarcs(Fetchdarcs),
+    Fetchfossil(Fetchfossil),
     Fetchgit(Fetchgit),
     Fetchhg(Fetchhg),
     Fetchsvn(Fetchsvn),
+    Fetchurl(Fetchurl),
+}
+
+// the URL of the repository a forge fetcher was called with,
+// None for the fetchers that take the URL or a package name instead
+pub fn forge_url<'b>(
+    fetcher: &FetcherFunction,
+    arg: impl Fn(&str) -> Result<Option<&'b str>>,
+) -> Result<Option<String>> {
+    let required = |name: &str| arg(name)?.with_context(|| format!("{name} is not specified"));
+
+    Ok(Some(match fetcher {
+        FetcherFunction::FetchFromGitHub => format!(
+            "https://{}/{}/{}",
+            arg("githubBase")?.unwrap_or("github.com"),
+            required("owner")?,
+            required("repo")?,
+        ),
+        FetcherFunction::FetchFromGitLab => {
+            let group = arg("group")?
+                .map(|group| format!("{group}/"))
+                .unwrap_or_default();
+            format!(
+                "https://{}/{group}{}/{}",
+                arg("domain")?.unwrap_or("gitlab.com"),
+                required("owner")?,
+                required("repo")?,
+            )
+        }
+        FetcherFunction::FetchFromGitea => format!(
+            "https://{}/{}/{}",
+            required("domain")?,
+            required("owner")?,
+            required("repo")?,
+        ),
+        FetcherFunction::FetchFromSourcehut => {
+            if arg("vc")?.is_some_and(|vc| vc != "git") {
+                bail!("only git repositories are supported");
+            }
+            format!(
+                "https://git.{}/{}/{}",
+                arg("domain")?.unwrap_or("sr.ht"),
+                required("owner")?,
+                required("repo")?,
+            )
+        }
+        FetcherFunction::FetchFromBitbucket => format!(
+            "https://bitbucket.org/{}/{}",
+            required("owner")?,
+            required("repo")?,
+        ),
+        FetcherFunction::FetchFromRepoOrCz => {
+            format!("https://repo.or.cz/{}", required("repo")?)
+        }
+        _ => return Ok(None),
+    }))
+}
+
+// the same from the JSON output
+pub fn json_forge_url(json: &Value) -> Result<Option<String>> {
+    let Some(fetcher) = json["fetcher"]
+        .as_str()
+        .and_then(|fetcher| FetcherFunction::from_str(fetcher, false).ok())
+    else {
+        return Ok(None);
+    };
+
+    forge_url(&fetcher, |name| Ok(json["args"][name].as_str()))
+}
+
+// the last component of the URL of a repository
+pub fn repo_name(url: &str) -> &str {
+    let name = url.trim_end_matches('/').rsplit('/').next().unwrap_or(url);
+    name.strip_suffix(".git").unwrap_or(name)
 }
 
 #[macro_export]
 macro_rules! impl_fetcher {
     ($t:ty) => {
         impl<'a> $crate::fetcher::Fetcher<'a> for $t {
-            fn fetch_nix(
+            fn prefetch(
                 &self,
-                out: &mut impl ::std::io::Write,
                 url: &'a $crate::Url,
                 rev: Option<String>,
+                pin: bool,
+                cache: bool,
+                hash_only: bool,
                 submodules: Option<bool>,
-                args: Vec<(String, String)>,
+                overwrites: &[(String, String)],
+                args: &[(String, String)],
                 args_str: Vec<(String, String)>,
-                overwrites: ::rustc_hash::FxHashMap<String, String>,
                 nixpkgs: String,
-                indent: String,
-            ) -> ::anyhow::Result<()> {
+            ) -> ::anyhow::Result<$crate::fetcher::Prefetched> {
                 use anyhow::Context;
 
                 let values = &self
                     .get_values(url)
-                    .with_context(|| format!("failed to parse {url}"))?;
-
-                let rev = match rev {
-                    Some(rev) => rev,
-                    None => self.fetch_rev(values)?,
-                };
+                    .with_context(|| self.parse_error(url))?;
 
-                let submodules = self.resolve_submodules(submodules);
-                let hash = self.fetch(values, &rev, submodules, &args, &args_str, nixpkgs)?;
+                let submodules = self.resolve_submodules(
+                    submodules,
+                    overwrites
+                        .iter()
+                        .rfind(|(key, _)| Some(key.as_str()) == Self::SUBMODULES_KEY)
+                        .map(|(_, value)| value.as_str()),
+                )?;
+                let (values, rev, args_str, hash) = self.resolve_and_fetch(
+                    url,
+                    values,
+                    rev,
+                    pin,
+                    cache,
+                    hash_only,
+                    submodules,
+                    args,
+                    args_str,
+                    |values, rev, args_str| {
+                        self.fetch(values, rev, submodules, args, args_str, nixpkgs)
+                    },
+                )?;
 
-                self.write_nix(out, values, rev, hash, submodules, args, args_str, overwrites, indent)
+                Ok($crate::fetcher::Prefetched {
+                    values: values.into(),
+                    rev,
+                    args_str,
+                    hash,
+                    submodules,
+                })
             }
 
-            fn fetch_hash(
+            fn write_prefetched_nix(
                 &self,
                 out: &mut impl ::std::io::Write,
-                url: &'a $crate::Url,
-                rev: Option<String>,
-                submodules: Option<bool>,
+                _: &'a $crate::Url,
+                prefetched: $crate::fetcher::Prefetched,
                 args: Vec<(String, String)>,
-                args_str: Vec<(String, String)>,
-                nixpkgs: String,
+                overwrites: ::indexmap::IndexMap<String, String>,
+                indent: String,
             ) -> ::anyhow::Result<()> {
-                use anyhow::Context;
-
-                let values = &self
-                    .get_values(url)
-                    .with_context(|| format!("failed to parse {url}"))?;
+                let values = &self.prefetched_values(&prefetched.values)?;
 
-                let rev = match rev {
-                    Some(rev) => rev,
-                    None => self.fetch_rev(values)?,
-                };
+                self.write_nix(
+                    out,
+                    values,
+                    prefetched.rev,
+                    prefetched.hash,
+                    prefetched.submodules,
+                    args,
+                    prefetched.args_str,
+                    overwrites,
+                    indent,
+                )
+            }
 
-                let submodules = self.resolve_submodules(submodules);
-                let hash = self.fetch(values, &rev, submodules, &args, &args_str, nixpkgs)?;
-                write!(out, "{}", hash)?;
+            fn prefetched_json(
+                &self,
+                _: &'a $crate::Url,
+                prefetched: $crate::fetcher::Prefetched,
+                args: Vec<(String, String)>,
+                overwrites: Vec<(String, String)>,
+                overwrites_str: Vec<(String, String)>,
+            ) -> ::anyhow::Result<::serde_json::Value> {
+                let values = &self.prefetched_values(&prefetched.values)?;
 
-                Ok(())
+                self.json(
+                    values,
+                    prefetched.rev,
+                    prefetched.hash,
+                    prefetched.submodules,
+                    args,
+                    prefetched.args_str,
+                    overwrites,
+                    overwrites_str,
+                )
             }
 
-            fn fetch_json(
+            fn write_known(
                 &self,
                 out: &mut impl ::std::io::Write,
                 url: &'a $crate::Url,
-                rev: Option<String>,
+                rev: String,
+                hash: String,
                 submodules: Option<bool>,
-                args: Vec<(String, String)>,
-                args_str: Vec<(String, String)>,
-                overwrites: Vec<(String, String)>,
-                overwrites_str: Vec<(String, String)>,
-                nixpkgs: String,
+                indent: String,
             ) -> ::anyhow::Result<()> {
                 use anyhow::Context;
 
                 let values = &self
                     .get_values(url)
-                    .with_context(|| format!("failed to parse {url}"))?;
-
-                let rev = match rev {
-                    Some(rev) => rev,
-                    None => self.fetch_rev(values)?,
-                };
-
-                let submodules = self.resolve_submodules(submodules);
-                let hash = self.fetch(values, &rev, submodules, &args, &args_str, nixpkgs)?;
+                    .with_context(|| self.parse_error(url))?;
+                let submodules = self.resolve_submodules(submodules, None)?;
 
-                self.write_json(
+                self.write_nix(
                     out,
                     values,
                     rev,
                     hash,
                     submodules,
-                    args,
-                    args_str,
-                    overwrites,
-                    overwrites_str,
+                    Vec::new(),
+                    Vec::new(),
+                    ::indexmap::IndexMap::new(),
+                    indent,
                 )
             }
 
             fn to_json(
                 &'a self,
-                out: &mut impl ::std::io::Write,
                 url: &'a $crate::Url,
                 rev: Option<String>,
-            ) -> ::anyhow::Result<()> {
+            ) -> ::anyhow::Result<::serde_json::Value> {
                 use anyhow::Context;
                 use serde_json::{json, Value};
 
                 let values = self
                     .get_values(url)
-                    .with_context(|| format!("failed to parse {url}"))?;
+                    .with_context(|| self.parse_error(url))?;
 
                 let mut fetcher_args = Value::from_iter(Self::KEYS.into_iter().zip(values));
 
@@ -211,16 +426,65 @@ macro_rules! impl_fetcher {
                 if let Some(rev) = rev {
                     fetcher_args[Self::REV_KEY] = json!(rev);
                 }
+                if self.private() {
+                    fetcher_args["private"] = json!(true);
+                }
 
-                serde_json::to_writer(
-                    out,
-                    &json!({
-                        "fetcher": Self::NAME,
-                        "args": fetcher_args,
-                    }),
-                )?;
+                Ok(json!({
+                    "fetcher": Self::NAME,
+                    "args": fetcher_args,
+                }))
+            }
+
+            fn compare(
+                &self,
+                url: &'a $crate::Url,
+                old: &str,
+                new: &str,
+            ) -> (Option<String>, Option<u64>) {
+                let Some(values) = self.get_values(url) else {
+                    return (None, None);
+                };
+
+                (
+                    self.compare_url(&values, old, new),
+                    self.count_commits(&values, old, new),
+                )
+            }
+
+            fn meta(&self, url: &'a $crate::Url, tag: Option<&str>) -> $crate::simple::Meta {
+                let Some(values) = self.get_values(url) else {
+                    return Default::default();
+                };
+
+                let mut meta = self.fetch_meta(&values).unwrap_or_default();
+                meta.changelog = tag.and_then(|tag| self.changelog_url(&values, tag));
+                meta
+            }
+
+            fn latest_rev(&self, url: &'a $crate::Url) -> ::anyhow::Result<String> {
+                use anyhow::Context;
+
+                let values = self
+                    .get_values(url)
+                    .with_context(|| self.parse_error(url))?;
+
+                self.fetch_rev(&values)
+            }
+
+            fn latest_tag(
+                &self,
+                url: &'a $crate::Url,
+                filter: &$crate::simple::TagFilter,
+            ) -> ::anyhow::Result<String> {
+                use anyhow::Context;
+
+                let values = self
+                    .get_values(url)
+                    .with_context(|| self.parse_error(url))?;
 
-                Ok(())
+                $crate::simple::latest_tag(self.fetch_tags(&values)?, filter)
+                    .with_context(|| format!("no matching tags found for {url}"))
             }
         }
     };
@@ -1,22 +1,169 @@
-use anyhow::Result;
+use std::collections::HashMap;
 
-use crate::{impl_fetcher, prefetch::url_prefetch, simple::SimpleFetcher, Url};
+use anyhow::{Context, Result};
+use serde::Deserialize;
 
-pub struct FetchPypi;
-impl_fetcher!(FetchPypi);
+use crate::{
+    http, impl_fetcher, info,
+    prefetch::{sri_from_hex, url_prefetch},
+    simple::SimpleFetcher,
+    warn, Url,
+};
 
-impl<'a> SimpleFetcher<'a, 1> for FetchPypi {
+pub struct FetchPypi<'a> {
+    pub wheel: Option<WheelTags<'a>>,
+    pub trust_upstream_hash: bool,
+}
+impl_fetcher!(FetchPypi<'a>);
+
+pub struct WheelTags<'a> {
+    pub python: Option<&'a str>,
+    pub abi: Option<&'a str>,
+    pub platform: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct Project {
+    info: Info,
+    releases: HashMap<String, Vec<File>>,
+}
+
+#[derive(Deserialize)]
+struct File {
+    #[serde(default)]
+    yanked: bool,
+}
+
+#[derive(Deserialize)]
+struct Info {
+    version: String,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    urls: Vec<Distribution>,
+}
+
+#[derive(Deserialize)]
+struct Distribution {
+    digests: Digests,
+    filename: String,
+    packagetype: String,
+    python_version: String,
+}
+
+#[derive(Deserialize)]
+struct Digests {
+    sha256: String,
+}
+
+const WHEEL_ARGS: [(&str, &str); 4] = [
+    ("dist", "py2.py3"),
+    ("python", "py2.py3"),
+    ("abi", "none"),
+    ("platform", "any"),
+];
+
+impl<'a> SimpleFetcher<'a, 1> for FetchPypi<'_> {
     const KEYS: [&'static str; 1] = ["pname"];
     const NAME: &'static str = "fetchPypi";
+    const PATH_FORMAT: Option<&'static str> = Some("/project/pname");
     const REV_KEY: &'static str = "version";
 
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
         let pname = url.path_segments().nth(1)?;
         (!pname.is_empty()).then_some([pname])
     }
+
+    fn fetch_rev(&self, [pname]: &[&str; 1]) -> Result<String> {
+        Ok(fetch_project(pname)?.info.version)
+    }
+
+    // releases whose files are all yanked, or that have none, cannot be fetched
+    fn fetch_tags(&self, [pname]: &[&str; 1]) -> Result<Vec<String>> {
+        Ok(fetch_project(pname)?
+            .releases
+            .into_iter()
+            .filter_map(|(version, files)| files.iter().any(|file| !file.yanked).then_some(version))
+            .collect())
+    }
+
+    fn infer_args(
+        &self,
+        [pname]: &[&str; 1],
+        version: &str,
+        _: &[(String, String)],
+        args_str: &mut Vec<(String, String)>,
+    ) -> Result<()> {
+        if args_str
+            .iter()
+            .any(|(key, _)| key == "extension" || key == "format")
+        {
+            return Ok(());
+        }
+
+        let urls = fetch_distributions(pname, version);
+        let sdist = urls
+            .iter()
+            .flatten()
+            .find(|dist| dist.packagetype == "sdist");
+
+        // packages without a source distribution can still be fetched as a pure Python wheel
+        let any = WheelTags {
+            python: None,
+            abi: None,
+            platform: Some("any"),
+        };
+        let tags = match (&self.wheel, sdist, &urls) {
+            (Some(tags), ..) => Some(tags),
+            (None, None, Some(urls)) if !urls.is_empty() => {
+                warn!("{pname} {version} has no source distribution, fetching a wheel instead");
+                Some(&any)
+            }
+            _ => None,
+        };
+
+        if let Some(tags) = tags {
+            let urls = urls.as_deref().with_context(|| {
+                format!("failed to fetch the distributions of {pname} {version}")
+            })?;
+            let (dist, [python, abi, platform]) = find_wheel(urls, tags)
+                .with_context(|| format!("no matching wheel found for {pname} {version}"))?;
+
+            args_str.push(("format".into(), "wheel".into()));
+            for ((key, default), value) in WHEEL_ARGS.into_iter().zip([dist, python, abi, platform])
+            {
+                if value != default {
+                    args_str.push((key.into(), value.into()));
+                }
+            }
+
+            return Ok(());
+        }
+
+        let Some(filename) = sdist.map(|dist| &dist.filename) else {
+            return Ok(());
+        };
+        let Some(ext) = ["tar.gz", "zip", "tar.bz2", "tar.xz", "tgz"]
+            .into_iter()
+            .find(|ext| filename.ends_with(&format!(".{ext}")))
+        else {
+            return Ok(());
+        };
+
+        if *filename != format!("{pname}-{version}.{ext}") {
+            warn!("the source distribution of {pname} {version} is named {filename}, which does not match the pname");
+        }
+
+        if ext != "tar.gz" {
+            args_str.push(("extension".into(), ext.into()));
+        }
+
+        Ok(())
+    }
 }
 
-impl FetchPypi {
+impl FetchPypi<'_> {
     fn fetch(
         &self,
         values @ [pname]: &[&str; 1],
@@ -27,13 +174,85 @@ impl FetchPypi {
         nixpkgs: String,
     ) -> Result<String> {
         match (args, args_str) {
-            ([], []) => url_prefetch(get_url(pname, version, "tar.gz"), false),
+            ([], []) => self.prefetch_dist(pname, version, get_url(pname, version, "tar.gz")),
             ([], [(key, ext)]) if key == "extension" => {
-                url_prefetch(get_url(pname, version, ext), false)
+                self.prefetch_dist(pname, version, get_url(pname, version, ext))
+            }
+            ([], [(key, format), rest @ ..])
+                if key == "format"
+                    && format == "wheel"
+                    && rest
+                        .iter()
+                        .all(|(key, _)| WHEEL_ARGS.iter().any(|(name, _)| name == key)) =>
+            {
+                let [dist, python, abi, platform] = WHEEL_ARGS.map(|(name, default)| {
+                    rest.iter()
+                        .find(|(key, _)| key == name)
+                        .map_or(default, |(_, value)| value.as_str())
+                });
+                self.prefetch_dist(
+                    pname,
+                    version,
+                    get_wheel_url(pname, version, dist, python, abi, platform),
+                )
             }
             _ => self.fetch_fod(values, version, submodules, args, args_str, nixpkgs),
         }
     }
+
+    fn prefetch_dist(&self, pname: &str, version: &str, url: String) -> Result<String> {
+        if self.trust_upstream_hash {
+            let filename = url.rsplit('/').next().unwrap_or_default();
+            let digest = fetch_distributions(pname, version).and_then(|urls| {
+                urls.into_iter()
+                    .find(|dist| dist.filename == filename)
+                    .map(|dist| dist.digests.sha256)
+            });
+            if let Some(hash) = digest.and_then(|digest| sri_from_hex(&digest).ok()) {
+                info!("using the digest of {filename} published by PyPI");
+                return Ok(hash);
+            }
+        }
+
+        url_prefetch(url, false)
+    }
+}
+
+fn fetch_project(pname: &str) -> Result<Project> {
+    http::get(&format!("https://pypi.org/pypi/{pname}/json"))
+        .call()?
+        .into_json()
+        .with_context(|| format!("failed to parse the metadata of {pname} from PyPI"))
+}
+
+fn fetch_distributions(pname: &str, version: &str) -> Option<Vec<Distribution>> {
+    let Release { urls } = http::get(&format!("https://pypi.org/pypi/{pname}/{version}/json"))
+        .call()
+        .ok()?
+        .into_json()
+        .ok()?;
+    Some(urls)
+}
+
+fn find_wheel<'a>(urls: &'a [Distribution], tags: &WheelTags) -> Option<(&'a str, [&'a str; 3])> {
+    let matches = |tag: &str, filter| match filter {
+        Some(filter) => tag.split('.').any(|tag| tag == filter),
+        None => true,
+    };
+
+    urls.iter()
+        .filter(|dist| dist.packagetype == "bdist_wheel")
+        .filter_map(|dist| {
+            let mut xs = dist.filename.strip_suffix(".whl")?.rsplitn(4, '-');
+            let platform = xs.next()?;
+            let abi = xs.next()?;
+            let python = xs.next()?;
+            (matches(python, tags.python)
+                && matches(abi, tags.abi)
+                && matches(platform, tags.platform))
+            .then_some((dist.python_version.as_str(), [python, abi, platform]))
+        })
+        .min_by_key(|(_, [_, _, platform])| *platform != "any")
 }
 
 fn get_url(pname: &str, version: &str, ext: &str) -> String {
@@ -42,3 +261,17 @@ fn get_url(pname: &str, version: &str, ext: &str) -> String {
     };
     format!("https://pypi.org/packages/source/{first}/{pname}/{pname}-{version}.{ext}")
 }
+
+fn get_wheel_url(
+    pname: &str,
+    version: &str,
+    dist: &str,
+    python: &str,
+    abi: &str,
+    platform: &str,
+) -> String {
+    let Some(first) = pname.chars().next() else {
+        unreachable!();
+    };
+    format!("https://files.pythonhosted.org/packages/{dist}/{first}/{pname}/{pname}-{version}-{python}-{abi}-{platform}.whl")
+}
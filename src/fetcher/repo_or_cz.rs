@@ -1,6 +1,9 @@
+use anyhow::Result;
+
 use crate::{
     impl_fetcher,
-    simple::{SimpleFetcher, SimpleUrlFetcher},
+    prefetch::{git_branch_rev, git_ref},
+    simple::{percent_encode, SimpleFetcher, SimpleUrlFetcher},
 };
 
 pub struct FetchFromRepoOrCz;
@@ -9,10 +12,21 @@ impl_fetcher!(FetchFromRepoOrCz);
 impl SimpleFetcher<'_, 1> for FetchFromRepoOrCz {
     const KEYS: [&'static str; 1] = ["repo"];
     const NAME: &'static str = "fetchFromRepoOrCz";
+
+    fn fetch_rev(&self, [repo]: &[&str; 1]) -> Result<String> {
+        git_ref(&format!("https://repo.or.cz/{repo}.git"), "HEAD")
+    }
+
+    fn fetch_branch_rev(&self, [repo]: &[&str; 1], branch: &str) -> Option<String> {
+        git_branch_rev(&format!("https://repo.or.cz/{repo}.git"), branch)
+    }
 }
 
 impl SimpleUrlFetcher<'_, 1> for FetchFromRepoOrCz {
     fn get_url(&self, [repo]: &[&str; 1], rev: &str) -> String {
-        format!("https://repo.or.cz/{repo}.git/snapshot/{rev}.tar.gz")
+        format!(
+            "https://repo.or.cz/{repo}.git/snapshot/{}.tar.gz",
+            percent_encode(rev)
+        )
     }
 }
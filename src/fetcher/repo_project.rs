@@ -0,0 +1,35 @@
+use anyhow::Result;
+
+use crate::{
+    impl_fetcher,
+    prefetch::git_ref,
+    simple::{SimpleFetcher, SimpleFodFetcher},
+    Url,
+};
+
+pub struct FetchRepoProject;
+impl_fetcher!(FetchRepoProject);
+
+impl<'a> SimpleFetcher<'a, 2> for FetchRepoProject {
+    const HASH_KEY: &'static str = "sha256";
+    const KEYS: [&'static str; 2] = ["name", "manifest"];
+    const NAME: &'static str = "fetchRepoProject";
+
+    // fetchRepoProject requires a name, use the name of the manifest repository
+    fn get_values(&self, url: &'a Url) -> Option<[&'a str; 2]> {
+        let manifest = url.as_str().trim_end_matches('/');
+        let name = manifest.rsplit('/').next()?;
+        let name = name.strip_suffix(".git").unwrap_or(name);
+        (!name.is_empty()).then_some([name, manifest])
+    }
+
+    fn fetch_rev(&self, [_, manifest]: &[&str; 2]) -> Result<String> {
+        git_ref(manifest, "HEAD")
+    }
+
+    fn fetch_branch_rev(&self, [_, manifest]: &[&str; 2], branch: &str) -> Option<String> {
+        git_ref(manifest, &format!("refs/heads/{branch}")).ok()
+    }
+}
+
+impl SimpleFodFetcher<'_, 2> for FetchRepoProject {}
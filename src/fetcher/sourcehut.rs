@@ -1,6 +1,9 @@
+use anyhow::{bail, Result};
+
 use crate::{
-    impl_fetcher,
-    simple::{SimpleFetcher, SimpleGitFetcher},
+    http, impl_fetcher,
+    prefetch::{git_branch_rev, git_ref},
+    simple::{percent_encode, SimpleFetcher, SimpleGitFetcher},
 };
 
 pub struct FetchFromSourcehut<'a>(pub Option<&'a str>);
@@ -14,14 +17,33 @@ impl<'a> SimpleFetcher<'a, 2> for FetchFromSourcehut<'a> {
     fn host(&self) -> Option<&str> {
         self.0
     }
+
+    // the sourcehut APIs require OAuth tokens even for public repositories
+    fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
+        git_ref(
+            &format!("https://{}/{owner}/{repo}", self.0.unwrap_or("git.sr.ht")),
+            "HEAD",
+        )
+    }
+
+    fn fetch_branch_rev(&self, [owner, repo]: &[&str; 2], branch: &str) -> Option<String> {
+        git_branch_rev(
+            &format!("https://{}/{owner}/{repo}", self.0.unwrap_or("git.sr.ht")),
+            branch,
+        )
+    }
 }
 
 impl<'a> SimpleGitFetcher<'a, 2> for FetchFromSourcehut<'a> {
     fn get_flake_ref(&self, [owner, repo]: &[&str; 2], rev: &str) -> String {
         if let Some(host) = self.0 {
-            format!("sourcehut:{owner}/{repo}/{rev}?host={host}")
+            format!(
+                "sourcehut:{owner}/{repo}/{}?host={}",
+                percent_encode(rev),
+                percent_encode(host),
+            )
         } else {
-            format!("sourcehut:{owner}/{repo}/{rev}")
+            format!("sourcehut:{owner}/{repo}/{}", percent_encode(rev))
         }
     }
 
@@ -31,4 +53,21 @@ impl<'a> SimpleGitFetcher<'a, 2> for FetchFromSourcehut<'a> {
             self.0.unwrap_or("git.sr.ht"),
         )
     }
+
+    fn get_archive_url(&self, [owner, repo]: &[&str; 2], rev: &str) -> Option<String> {
+        Some(format!(
+            "https://{}/{owner}/{repo}/archive/{}.tar.gz",
+            self.0.unwrap_or("git.sr.ht"),
+            percent_encode(rev),
+        ))
+    }
+}
+
+pub fn find_sourcehut_host(path: &str) -> Result<&'static str> {
+    for host in ["git.sr.ht", "hg.sr.ht"] {
+        if http::head(&format!("https://{host}{path}")).call().is_ok() {
+            return Ok(host);
+        }
+    }
+    bail!("{path} was not found on git.sr.ht or hg.sr.ht");
 }
@@ -1,5 +1,8 @@
+use anyhow::{bail, Result};
+
 use crate::{
     impl_fetcher,
+    prefetch::svn_last_changed_rev,
     simple::{SimpleFetcher, SimpleFodFetcher},
     Url,
 };
@@ -13,8 +16,55 @@ impl<'a> SimpleFetcher<'a, 1> for Fetchsvn {
     const NAME: &'static str = "fetchsvn";
 
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
-        Some([url.as_str()])
+        Some([url.as_str().trim_end_matches('/')])
+    }
+
+    fn fetch_rev(&self, [url]: &[&str; 1]) -> Result<String> {
+        svn_last_changed_rev(url)
+    }
+
+    fn resolve_rev(
+        &self,
+        values @ [url]: &[&str; 1],
+        rev: Option<String>,
+        _: bool,
+    ) -> Result<String> {
+        let Some(rev) = rev else {
+            return self.fetch_rev(values);
+        };
+
+        if rev.bytes().all(|c| c.is_ascii_digit()) {
+            return Ok(rev);
+        }
+
+        match split_layout(url) {
+            _ if rev == "HEAD" => self.fetch_rev(values),
+            Some(Some(name)) if name == rev => self.fetch_rev(values),
+            Some(_) => bail!("{rev} is not a revision number"),
+            None => bail!(
+                "{rev} is not a revision number, use {url}/tags/{rev} or {url}/branches/{rev} \
+                to fetch a tag or a branch",
+            ),
+        }
     }
 }
 
 impl SimpleFodFetcher<'_, 1> for Fetchsvn {}
+
+// the tag or branch name of the standard layout, None if the url is outside of it,
+// the url itself is kept as is since it can point to a subdirectory
+fn split_layout(url: &str) -> Option<Option<&str>> {
+    for (i, _) in url.match_indices('/') {
+        let mut xs = url[i + 1 ..].split('/');
+        match xs.next()? {
+            "trunk" => return Some(None),
+            "tags" | "branches" => match xs.next() {
+                Some(name) if !name.is_empty() => return Some(Some(name)),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    None
+}
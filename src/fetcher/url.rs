@@ -0,0 +1,292 @@
+use std::{fmt::Write as _, io::Write};
+
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use crate::{
+    fetcher::{Fetcher, Prefetched},
+    info, log,
+    prefetch::{fod_prefetch, format_hash, hash_attr, url_prefetch},
+    simple::{nix_escape, Meta, TagFilter},
+    Url,
+};
+
+// prefixes of URLs that nixpkgs has mirror lists for, without the scheme
+const MIRRORS: [(&str, &str); 8] = [
+    ("archive.apache.org/dist/", "apache"),
+    ("dlcdn.apache.org/", "apache"),
+    ("downloads.apache.org/", "apache"),
+    ("downloads.sourceforge.net/", "sourceforge"),
+    ("ftp.gnu.org/gnu/", "gnu"),
+    ("ftpmirror.gnu.org/", "gnu"),
+    ("prdownloads.sourceforge.net/", "sourceforge"),
+    ("www.apache.org/dist/", "apache"),
+];
+
+pub struct Fetchurl;
+
+impl Fetchurl {
+    // the mirror URL comes first so the direct URL is only used when all mirrors fail
+    fn urls(&self, url: &Url) -> Vec<String> {
+        let url = url.as_str();
+        let mirror = url.split_once("://").and_then(|(_, rest)| {
+            MIRRORS.iter().find_map(|(prefix, mirror)| {
+                Some(format!("mirror://{mirror}/{}", rest.strip_prefix(prefix)?))
+            })
+        });
+
+        mirror.into_iter().chain([url.into()]).collect()
+    }
+
+    fn fetch(
+        &self,
+        urls: &[String],
+        rev: Option<String>,
+        args: &[(String, String)],
+        args_str: &[(String, String)],
+        nixpkgs: &str,
+    ) -> Result<String> {
+        if rev.is_some() {
+            bail!("fetchurl does not support revisions");
+        }
+        log::event("fetcher", json!({ "fetcher": "fetchurl", "urls": urls }));
+
+        if !args.is_empty() || !args_str.is_empty() {
+            let mut expr = format!(
+                r#"(import({nixpkgs}){{}}).fetchurl{{urls=[{}];hash="sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";"#,
+                urls.iter()
+                    .map(|url| format!(r#""{}""#, nix_escape(url)))
+                    .join(" "),
+            );
+            for (key, value) in args {
+                write!(expr, "{key}={value};")?;
+            }
+            for (key, value) in args_str {
+                write!(expr, r#"{key}="{value}";"#)?;
+            }
+            expr.push('}');
+            let hash = log::prefetch(&urls.join(" "), || fod_prefetch(expr))?;
+            log::event("hash", json!({ "hash": &hash, "cached": false }));
+            return Ok(hash);
+        }
+
+        let [mirrors @ .., url] = urls else {
+            bail!("no URLs to fetch");
+        };
+        let hash = log::prefetch(url, || url_prefetch(url.clone(), false))?;
+
+        // fetchurl uses the first mirror that responds, which is what the build will do as well
+        for mirror in mirrors {
+            info!("verifying {hash} against {mirror}");
+            let mirror_hash = log::prefetch(mirror, || {
+                fod_prefetch(format!(
+                    r#"(import({nixpkgs}){{}}).fetchurl{{url="{}";hash="sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";}}"#,
+                    nix_escape(mirror),
+                ))
+            })?;
+            if mirror_hash != hash {
+                bail!(
+                    "the mirrors do not match the URL\n  {url}: {hash}\n  {mirror}: {mirror_hash}"
+                );
+            }
+        }
+
+        log::event("hash", json!({ "hash": &hash, "cached": false }));
+        Ok(hash)
+    }
+
+    fn urls_arg(urls: &[String]) -> (&'static str, Value) {
+        match urls {
+            [url] => ("url", json!(url)),
+            urls => (
+                "urls",
+                json!({
+                    "type": "nix",
+                    "value": format!(
+                        "[ {} ]",
+                        urls.iter()
+                            .map(|url| format!(r#""{}""#, nix_escape(url)))
+                            .join(" "),
+                    ),
+                }),
+            ),
+        }
+    }
+}
+
+impl<'a> Fetcher<'a> for Fetchurl {
+    fn prefetch(
+        &self,
+        url: &'a Url,
+        rev: Option<String>,
+        _: bool,
+        _: bool,
+        _: bool,
+        _: Option<bool>,
+        _: &[(String, String)],
+        args: &[(String, String)],
+        args_str: Vec<(String, String)>,
+        nixpkgs: String,
+    ) -> Result<Prefetched> {
+        let urls = self.urls(url);
+        let hash = self.fetch(&urls, rev, args, &args_str, &nixpkgs)?;
+
+        Ok(Prefetched {
+            values: urls,
+            rev: String::new(),
+            args_str,
+            hash,
+            submodules: false,
+        })
+    }
+
+    fn write_prefetched_nix(
+        &self,
+        out: &mut impl Write,
+        _: &'a Url,
+        prefetched: Prefetched,
+        args: Vec<(String, String)>,
+        overwrites: IndexMap<String, String>,
+        indent: String,
+    ) -> Result<()> {
+        let mut overwrites = overwrites;
+        let urls = prefetched.values;
+
+        writeln!(out, "fetchurl {{")?;
+
+        if let Some(url) = overwrites.shift_remove("url") {
+            writeln!(out, "{indent}  url = {url};")?;
+        } else if let Some(urls) = overwrites.shift_remove("urls") {
+            writeln!(out, "{indent}  urls = {urls};")?;
+        } else if let [url] = &urls[..] {
+            writeln!(out, r#"{indent}  url = "{}";"#, nix_escape(url))?;
+        } else {
+            writeln!(out, "{indent}  urls = [")?;
+            for url in &urls {
+                writeln!(out, r#"{indent}    "{}""#, nix_escape(url))?;
+            }
+            writeln!(out, "{indent}  ];")?;
+        }
+
+        let hash_key = hash_attr("hash");
+        if let Some(hash) = overwrites.shift_remove(hash_key) {
+            writeln!(out, "{indent}  {hash_key} = {hash};")?;
+        } else {
+            writeln!(
+                out,
+                r#"{indent}  {hash_key} = "{}";"#,
+                format_hash(&prefetched.hash)?,
+            )?;
+        }
+
+        for (key, value) in args {
+            let value = overwrites.shift_remove(&key).unwrap_or(value);
+            writeln!(out, "{indent}  {key} = {value};")?;
+        }
+        for (key, value) in prefetched.args_str {
+            if let Some(value) = overwrites.shift_remove(&key) {
+                writeln!(out, "{indent}  {key} = {value};")?;
+            } else {
+                writeln!(out, r#"{indent}  {key} = "{value}";"#)?;
+            }
+        }
+
+        for (key, value) in overwrites {
+            writeln!(out, "{indent}  {key} = {value};")?;
+        }
+
+        write!(out, "{indent}}}")?;
+
+        Ok(())
+    }
+
+    fn prefetched_json(
+        &self,
+        _: &'a Url,
+        prefetched: Prefetched,
+        args: Vec<(String, String)>,
+        overwrites: Vec<(String, String)>,
+        overwrites_str: Vec<(String, String)>,
+    ) -> Result<Value> {
+        let (key, value) = Self::urls_arg(&prefetched.values);
+        let hash_key = hash_attr("hash");
+        let mut fetcher_args = json!({
+            key: value,
+            hash_key: format_hash(&prefetched.hash)?,
+        });
+
+        for (key, value) in args {
+            fetcher_args[key] = json!({
+                "type": "nix",
+                "value": value,
+            });
+        }
+        for (key, value) in prefetched.args_str {
+            fetcher_args[key] = json!(value);
+        }
+
+        for (key, value) in overwrites {
+            fetcher_args[key] = json!({
+                "type": "nix",
+                "value": value,
+            })
+        }
+        for (key, value) in overwrites_str {
+            fetcher_args[key] = json!(value);
+        }
+
+        Ok(json!({
+            "fetcher": "fetchurl",
+            "args": fetcher_args,
+        }))
+    }
+
+    fn write_known(
+        &self,
+        _: &mut impl Write,
+        _: &'a Url,
+        _: String,
+        _: String,
+        _: Option<bool>,
+        _: String,
+    ) -> Result<()> {
+        bail!("fetchurl does not support known hashes");
+    }
+
+    fn to_json(&'a self, url: &'a Url, rev: Option<String>) -> Result<Value> {
+        if rev.is_some() {
+            bail!("fetchurl does not support revisions");
+        }
+
+        let (key, value) = Self::urls_arg(&self.urls(url));
+        serde_json::to_writer(
+            out,
+            &json!({
+                "fetcher": "fetchurl",
+                "args": {
+                    key: value,
+                },
+            }),
+        )?;
+
+        Ok(())
+    }
+
+    fn compare(&self, _: &'a Url, _: &str, _: &str) -> (Option<String>, Option<u64>) {
+        (None, None)
+    }
+
+    fn meta(&self, _: &'a Url, _: Option<&str>) -> Meta {
+        Meta::default()
+    }
+
+    fn latest_rev(&self, _: &'a Url) -> Result<String> {
+        bail!("fetchurl does not support revisions");
+    }
+
+    fn latest_tag(&self, _: &'a Url, _: &TagFilter) -> Result<String> {
+        bail!("fetchurl does not support revisions");
+    }
+}
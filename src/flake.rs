@@ -0,0 +1,190 @@
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    cli::FlakeFormat,
+    fetcher::{json_forge_url, repo_name, Fetchgit},
+    prefetch::git_ls_remote,
+    simple::{nix_attr_name, percent_encode, SimpleFetcher},
+};
+
+pub fn write_flake(
+    out: &mut impl Write,
+    format: &FlakeFormat,
+    url: &str,
+    json: &Value,
+) -> Result<()> {
+    let args = &json["args"];
+    let arg = |key| args[key].as_str();
+    let fetcher = json["fetcher"]
+        .as_str()
+        .context("failed to find the fetcher")?;
+
+    // flake inputs have no equivalent of the other fetcher arguments
+    let known = [
+        "owner",
+        "repo",
+        "group",
+        "domain",
+        "githubBase",
+        "url",
+        "rev",
+        "hash",
+        "fetchSubmodules",
+    ];
+    if let Some(key) = args
+        .as_object()
+        .into_iter()
+        .flat_map(Map::keys)
+        .find(|key| !known.contains(&key.as_str()))
+    {
+        bail!("{fetcher} with {key} cannot be expressed as a flake input");
+    }
+
+    let rev = arg("rev").context("failed to find the revision")?;
+    let hash = arg("hash").context("failed to find the hash")?;
+    // fetchSubmodules is only written when it differs from the fetcher's default
+    let submodules = args["fetchSubmodules"].as_bool().unwrap_or(
+        fetcher == <Fetchgit as SimpleFetcher<1>>::NAME
+            && <Fetchgit as SimpleFetcher<1>>::SUBMODULES_DEFAULT,
+    );
+
+    let forge_url = || {
+        json_forge_url(json)?.with_context(|| format!("failed to find the repository of {fetcher}"))
+    };
+
+    let mut locked = Map::new();
+    let (flake_ref, repo_url) = match (fetcher, arg("owner"), arg("repo")) {
+        ("fetchFromGitHub", Some(owner), Some(repo)) if !submodules => {
+            let host = arg("githubBase");
+            locked.insert("type".into(), json!("github"));
+            locked.insert("owner".into(), json!(owner));
+            locked.insert("repo".into(), json!(repo));
+            if let Some(host) = host {
+                locked.insert("host".into(), json!(host));
+            }
+            (
+                with_host(
+                    format!("github:{owner}/{repo}/{}", percent_encode(rev)),
+                    host,
+                ),
+                forge_url()?,
+            )
+        }
+
+        ("fetchFromGitLab", Some(owner), Some(repo)) if !submodules => {
+            let host = arg("domain");
+            let owner = match arg("group") {
+                Some(group) => format!("{group}%2F{owner}"),
+                None => owner.into(),
+            };
+            locked.insert("type".into(), json!("gitlab"));
+            locked.insert("owner".into(), json!(owner));
+            locked.insert("repo".into(), json!(repo));
+            if let Some(host) = host {
+                locked.insert("host".into(), json!(host));
+            }
+            (
+                with_host(
+                    format!("gitlab:{owner}/{repo}/{}", percent_encode(rev)),
+                    host,
+                ),
+                forge_url()?,
+            )
+        }
+
+        ("fetchFromSourcehut", Some(owner), Some(repo)) if !submodules => {
+            let host = arg("domain");
+            locked.insert("type".into(), json!("sourcehut"));
+            locked.insert("owner".into(), json!(owner));
+            locked.insert("repo".into(), json!(repo));
+            if let Some(host) = host {
+                locked.insert("host".into(), json!(host));
+            }
+            (
+                with_host(
+                    format!("sourcehut:{owner}/{repo}/{}", percent_encode(rev)),
+                    host,
+                ),
+                forge_url()?,
+            )
+        }
+
+        // forges with submodules are fetched with fetchgit
+        ("fetchgit" | "fetchFromGitHub" | "fetchFromGitLab" | "fetchFromSourcehut", ..) => {
+            let repo_url = match arg("url") {
+                Some(url) => url.into(),
+                None => url.strip_prefix("git+").unwrap_or(url).into(),
+            };
+            locked.insert("type".into(), json!("git"));
+            locked.insert("url".into(), json!(repo_url));
+            if submodules {
+                locked.insert("submodules".into(), json!(true));
+            }
+
+            let key = if is_commit(rev) { "rev" } else { "ref" };
+            let mut flake_ref = format!("git+{repo_url}?{key}={}", percent_encode(rev));
+            if submodules {
+                flake_ref.push_str("&submodules=1");
+            }
+            (flake_ref, repo_url)
+        }
+
+        _ => bail!("{fetcher} cannot be expressed as a flake input"),
+    };
+
+    match format {
+        FlakeFormat::Input => {
+            let name = arg("repo").unwrap_or_else(|| repo_name(&repo_url));
+            write!(
+                out,
+                r#"inputs.{}.url = "{flake_ref}";"#,
+                nix_attr_name(name)
+            )?;
+        }
+
+        FlakeFormat::Lock => {
+            let rev = if is_commit(rev) {
+                rev.into()
+            } else {
+                resolve_commit(&repo_url, rev)?
+            };
+            locked.insert("rev".into(), json!(rev));
+            locked.insert("narHash".into(), json!(hash));
+            serde_json::to_writer(out, &locked)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_commit(rev: &str) -> bool {
+    rev.len() == 40 && rev.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+fn with_host(flake_ref: String, host: Option<&str>) -> String {
+    match host {
+        Some(host) => format!("{flake_ref}?host={}", percent_encode(host)),
+        None => flake_ref,
+    }
+}
+
+// flake.lock only contains commits, annotated tags are resolved to the commit they point to
+fn resolve_commit(url: &str, rev: &str) -> Result<String> {
+    let refs = git_ls_remote(url)?;
+    let find = |target: &str| {
+        refs.lines().find_map(|line| {
+            let (sha, name) = line.split_once('\t')?;
+            (name == target).then_some(sha)
+        })
+    };
+
+    find(&format!("refs/tags/{rev}^{{}}"))
+        .or_else(|| find(&format!("refs/tags/{rev}")))
+        .or_else(|| find(&format!("refs/heads/{rev}")))
+        .or_else(|| find(rev))
+        .map(Into::into)
+        .with_context(|| format!("failed to resolve {rev} to a commit in {url}"))
+}
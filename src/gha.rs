@@ -0,0 +1,54 @@
+use std::{
+    env,
+    fs::OpenOptions,
+    io::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// https://docs.github.com/en/actions/using-workflows/workflow-commands-for-github-actions
+pub fn escape(message: &str) -> String {
+    message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+// writes the fetcher, rev, version, and hash from the json output to $GITHUB_OUTPUT
+pub fn set_outputs(json: &Value) -> Result<()> {
+    let path = env::var_os("GITHUB_OUTPUT").context("GITHUB_OUTPUT is not set")?;
+    let args = &json["args"];
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context("failed to open GITHUB_OUTPUT")?;
+
+    if let Some(fetcher) = json["fetcher"].as_str() {
+        writeln!(file, "fetcher={fetcher}")?;
+    }
+    for (output, keys) in [
+        ("rev", &["rev", "tag"][..]),
+        ("version", &["version"]),
+        ("hash", &["hash", "sha256"]),
+    ] {
+        if let Some(value) = keys.iter().find_map(|key| args[key].as_str()) {
+            writeln!(file, "{output}={value}")?;
+        }
+    }
+
+    Ok(())
+}
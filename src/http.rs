@@ -0,0 +1,131 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, OnceLock},
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context, Result};
+use data_encoding::BASE64;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, RootCertStore, ServerName,
+};
+use serde::de::DeserializeOwned;
+use ureq::{Agent, AgentBuilder, Request, Response};
+
+use crate::audit::AuditMiddleware;
+
+static AGENT: OnceLock<Agent> = OnceLock::new();
+
+// share one agent so connections to the same host are kept alive across API calls
+fn agent() -> &'static Agent {
+    AGENT.get_or_init(|| builder().build())
+}
+
+fn builder() -> AgentBuilder {
+    AgentBuilder::new().middleware(AuditMiddleware)
+}
+
+pub fn get(url: &str) -> Request {
+    agent().get(url)
+}
+
+pub fn head(url: &str) -> Request {
+    agent().head(url)
+}
+
+// follows the next links of the Link header, which GitHub, GitLab, and Gitea all paginate with
+pub fn get_pages<T: DeserializeOwned>(
+    url: String,
+    mut get: impl FnMut(&str) -> Result<Response>,
+) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+    let mut next = Some(url);
+    while let Some(url) = next {
+        let resp = get(&url)?;
+        next = resp.header("link").and_then(next_link);
+        items.extend(resp.into_json::<Vec<T>>()?);
+    }
+
+    Ok(items)
+}
+
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == r#"rel="next""#)
+            .then(|| url.trim().strip_prefix('<')?.strip_suffix('>'))
+            .flatten()
+            .map(Into::into)
+    })
+}
+
+// has to be called before the first request, the agent is not rebuilt afterwards
+pub fn configure_tls(cacert: Option<&Path>, insecure: bool) -> Result<()> {
+    let builder = ClientConfig::builder().with_safe_defaults();
+    let config = if insecure {
+        builder
+            .with_custom_certificate_verifier(Arc::new(NoVerifier))
+            .with_no_client_auth()
+    } else if let Some(cacert) = cacert {
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(cacert)? {
+            roots.add(&Certificate(cert))?;
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    } else {
+        return Ok(());
+    };
+
+    let _ = AGENT.set(builder().tls_config(Arc::new(config)).build());
+
+    Ok(())
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let pem =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+
+    let mut certs = Vec::new();
+    let mut cert: Option<String> = None;
+    for line in pem.lines().map(str::trim) {
+        if line == "-----BEGIN CERTIFICATE-----" {
+            cert = Some(String::new());
+        } else if line == "-----END CERTIFICATE-----" {
+            if let Some(cert) = cert.take() {
+                certs.push(
+                    BASE64
+                        .decode(cert.as_bytes())
+                        .with_context(|| format!("invalid certificate in {}", path.display()))?,
+                );
+            }
+        } else if let Some(cert) = &mut cert {
+            cert.push_str(line);
+        }
+    }
+
+    if certs.is_empty() {
+        bail!("no certificates found in {}", path.display());
+    }
+
+    Ok(certs)
+}
+
+struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _: &Certificate,
+        _: &[Certificate],
+        _: &ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::OnceLock,
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use itertools::Itertools;
+use nu_glob::Pattern;
+use serde::Deserialize;
+
+use crate::{cli::FetcherFunction, warn};
+
+static INSTANCES: OnceLock<Instances> = OnceLock::new();
+
+pub struct Instance {
+    pub fetcher: FetcherFunction,
+    pub submodules: Option<bool>,
+    pub args: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+struct Instances {
+    hosts: HashMap<String, Instance>,
+    globs: Vec<(Pattern, Instance)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    instances: BTreeMap<String, InstanceConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceConfig {
+    fetcher: String,
+    submodules: Option<bool>,
+    #[serde(default)]
+    args: BTreeMap<String, String>,
+}
+
+impl Instances {
+    fn insert(&mut self, host: &str, instance: Instance) -> Result<()> {
+        let host = host.to_ascii_lowercase();
+        if host.contains(['*', '?', '[']) {
+            self.globs.push((Pattern::new(&host)?, instance));
+        } else {
+            self.hosts.insert(host, instance);
+        }
+        Ok(())
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("nurl").join("config.toml"))
+}
+
+// one host and fetcher per line
+fn bundled() -> Instances {
+    let mut instances = Instances::default();
+    for line in include_str!("instances.txt").lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((host, fetcher)) = line.split_whitespace().collect_tuple() else {
+            continue;
+        };
+        match FetcherFunction::from_str(fetcher, false) {
+            Ok(fetcher) => {
+                let instance = Instance {
+                    fetcher,
+                    submodules: None,
+                    args: Vec::new(),
+                };
+                instances.hosts.insert(host.into(), instance);
+            }
+            Err(e) => warn!("instances.txt: {e}"),
+        }
+    }
+    instances
+}
+
+// reads the instances in $XDG_CONFIG_HOME/nurl/config.toml,
+// until then only the bundled instances are recognized
+pub fn load_config() -> Result<()> {
+    if INSTANCES.get().is_some() {
+        return Ok(());
+    }
+
+    let mut instances = bundled();
+    if let Some(path) = config_path() {
+        match fs::read_to_string(&path) {
+            Ok(src) => parse_config(&src, &mut instances)
+                .with_context(|| format!("failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", path.display()));
+            }
+        }
+    }
+
+    // fails only when another thread fell back to the bundled instances in the meantime
+    let _ = INSTANCES.set(instances);
+    Ok(())
+}
+
+fn parse_config(src: &str, instances: &mut Instances) -> Result<()> {
+    let config: Config = toml_edit::de::from_str(src)?;
+    for (host, config) in config.instances {
+        let fetcher = FetcherFunction::from_str(&config.fetcher, false)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("invalid fetcher for {host}"))?;
+        let instance = Instance {
+            fetcher,
+            submodules: config.submodules,
+            args: config.args.into_iter().collect(),
+        };
+        instances
+            .insert(&host, instance)
+            .with_context(|| format!("invalid glob {host}"))?;
+    }
+
+    Ok(())
+}
+
+// the user's instances take precedence over the bundled ones, exact hosts over globs
+pub fn find_instance(host: &str) -> Option<&'static Instance> {
+    let instances = INSTANCES.get_or_init(bundled);
+
+    let host = host.to_ascii_lowercase();
+    instances.hosts.get(&host).or_else(|| {
+        instances
+            .globs
+            .iter()
+            .rev()
+            .find_map(|(glob, instance)| glob.matches(&host).then_some(instance))
+    })
+}
@@ -0,0 +1,101 @@
+use std::fmt::Write;
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+
+use crate::{
+    cache::{self, Entry},
+    cli::Lang,
+    info,
+    prefetch::fod_prefetch,
+    simple::nix_value,
+};
+
+// builds the fixed-output derivation of the dependencies against the fetched source
+pub fn dependency_hash(
+    lang: &Lang,
+    json: &Value,
+    nixpkgs: &str,
+    cache: bool,
+) -> Result<(&'static str, String)> {
+    let src = source_expr(json, nixpkgs)?;
+    let fake = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    let (key, expr) = match lang {
+        Lang::Rust => (
+            "cargoHash",
+            format!(
+                r#"(import({nixpkgs}){{}}).rustPlatform.fetchCargoVendor{{src={src};hash="{fake}";}}"#,
+            ),
+        ),
+        Lang::Go => (
+            "vendorHash",
+            format!(
+                r#"((import({nixpkgs}){{}}).buildGoModule{{pname="source";version="0";src={src};vendorHash="{fake}";}}).goModules"#,
+            ),
+        ),
+        Lang::Npm => (
+            "npmDepsHash",
+            format!(r#"(import({nixpkgs}){{}}).fetchNpmDeps{{src={src};hash="{fake}";}}"#),
+        ),
+    };
+
+    // the source expression contains its hash, so the dependencies are fixed as well,
+    // except for builtins.fetchGit, which takes no hash and could refer to a branch
+    let cache_key =
+        (cache && !src.starts_with("builtins.fetchGit")).then(|| json!([key, src]).to_string());
+    if let Some(entry) = cache_key.as_deref().and_then(cache::get) {
+        info!("using the cached {key}");
+        return Ok((key, entry.hash));
+    }
+
+    info!("computing {key}");
+    let hash = fod_prefetch(expr)?;
+
+    // only the hash is used, there are no values to resolve for the dependencies
+    if let Some(cache_key) = cache_key {
+        cache::insert(
+            cache_key,
+            Entry {
+                values: Vec::new(),
+                rev: String::new(),
+                args_str: Vec::new(),
+                hash: hash.clone(),
+            },
+        );
+    }
+
+    Ok((key, hash))
+}
+
+// turns the output of --json back into a Nix expression
+fn source_expr(json: &Value, nixpkgs: &str) -> Result<String> {
+    let fetcher = json["fetcher"]
+        .as_str()
+        .context("failed to find the fetcher")?;
+    let args = json["args"]
+        .as_object()
+        .context("failed to find the arguments")?;
+
+    let mut expr = if fetcher == "builtins.fetchGit" {
+        fetcher.into()
+    } else {
+        format!("(import({nixpkgs}){{}}).{fetcher}")
+    };
+
+    expr.push('{');
+    for (key, value) in args {
+        match value.get("type").and_then(Value::as_str) {
+            Some("nix") => {
+                let value = value["value"]
+                    .as_str()
+                    .with_context(|| format!("failed to find the value of {key}"))?;
+                write!(expr, "{key}={value};")?;
+            }
+            _ => write!(expr, "{key}={};", nix_value(value, ""))?,
+        }
+    }
+    expr.push('}');
+
+    Ok(expr)
+}
@@ -0,0 +1,1134 @@
+//! Generate Nix fetcher calls from repository URLs
+//!
+//! ```no_run
+//! let mut request = nurl::NurlRequest::new("https://github.com/nix-community/patsh");
+//! request.rev = Some("v0.2.0".into());
+//! let output = request.fetch_json()?;
+//! println!("{} {:?}", output.fetcher, output.hash());
+//! # anyhow::Ok(())
+//! ```
+
+#![allow(clippy::too_many_arguments)]
+
+mod api;
+mod audit;
+mod auth;
+mod batch;
+mod cache;
+mod check;
+mod cli;
+mod convert;
+mod cross_check;
+mod fetcher;
+mod flake;
+mod gha;
+mod http;
+mod instances;
+mod lang;
+mod license;
+mod lock;
+mod log;
+mod prefetch;
+mod sbom;
+mod simple;
+mod sources;
+mod update;
+mod watch;
+
+use std::{
+    env,
+    fmt::{self, Display, Formatter},
+    fs,
+    io::{stdout, Write},
+    str::Split,
+};
+
+use anyhow::{bail, Context, Result};
+use bstr::ByteSlice;
+use clap::ValueEnum;
+use gix_url::Scheme;
+use indexmap::IndexMap;
+use is_terminal::IsTerminal;
+use itertools::Itertools;
+use nu_glob::Pattern;
+use semver::VersionReq;
+use serde_json::{json, Value};
+
+pub use crate::{
+    api::{NurlOutput, NurlRequest},
+    cli::{FetcherFunction, Opts},
+};
+use crate::{
+    batch::batch,
+    check::check,
+    cli::{Command, HashAttr, HashFormat, PypiFormat},
+    convert::convert,
+    cross_check::cross_check,
+    fetcher::{
+        find_sourcehut_host, BuiltinsFetchGit, FetchCrate, FetchFromBitbucket, FetchFromGitHub,
+        FetchFromGitLab, FetchFromGitea, FetchFromGitiles, FetchFromRepoOrCz, FetchFromSourcehut,
+        FetchHex, FetchPypi, FetchRepoProject, Fetchbzr, Fetchdarcs, Fetcher, FetcherDispatch,
+        Fetchfossil, Fetchgit, Fetchhg, Fetchsvn, Fetchurl, Registry, WheelTags,
+    },
+    flake::write_flake,
+    instances::{find_instance, Instance},
+    lang::dependency_hash,
+    license::license_expr,
+    lock::from_lock,
+    prefetch::{
+        fod_prefetch, format_hash, git_ls_remote, git_verify_signature, nix_parse, verify, DryRun,
+    },
+    sbom::write_sbom,
+    simple::{nix_escape, nix_value, TagFilter},
+    sources::{lock, sync},
+    update::{outdated, update},
+    watch::watch,
+};
+
+pub struct Url<'a> {
+    url: &'a str,
+    path: &'a str,
+}
+
+impl Url<'_> {
+    fn as_str(&self) -> &str {
+        self.url
+    }
+
+    fn path(&self) -> &str {
+        self.path
+    }
+
+    fn path_segments(&self) -> Split<char> {
+        self.path.split('/')
+    }
+}
+
+impl Display for Url<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub enum GitScheme {
+    Yes,
+    No,
+    Plus,
+}
+
+/// How a run of the command line interface ended, which the binary chooses the exit code from
+pub enum Outcome {
+    /// The output was written
+    Success,
+    /// --check or the outdated subcommand found a newer revision
+    Outdated,
+    /// The error was already reported on stderr
+    Failure,
+}
+
+/// Run nurl with the parsed command line options, writing the output to stdout
+/// and reporting errors on stderr
+///
+/// This configures process-wide state like the environment of the spawned commands,
+/// use [`NurlRequest`] to fetch URLs from other programs
+#[doc(hidden)]
+pub fn run(opts: Opts) -> Outcome {
+    if opts.gha {
+        gha::enable();
+    }
+    log::set_format(&opts.log_format);
+
+    match run_opts(&opts) {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            if gha::enabled() {
+                eprintln!("::error::{}", gha::escape(&format!("{e:#}")));
+            }
+            if log::json() {
+                log::event("error", json!({ "message": format!("{e:#}") }));
+            } else {
+                eprintln!("Error: {e:?}");
+            }
+            Outcome::Failure
+        }
+    }
+}
+
+fn run_opts(opts: &Opts) -> Result<Outcome> {
+    let out = &mut stdout().lock();
+
+    if let Some(path) = &opts.audit_log {
+        audit::open(path)?;
+    }
+
+    instances::load_config()?;
+
+    if let Some(token) = &opts.token {
+        match gix_url::Url::try_from(opts.url.as_str())
+            .ok()
+            .and_then(|url| url.host().map(Into::into))
+        {
+            Some(host) => auth::set_token(host, token.clone()),
+            None => warn!("ignoring --token, it is only used for the host of the URL"),
+        }
+    }
+
+    if opts.refresh {
+        cache::refresh();
+    }
+
+    // the hashes of existing files and lock files are replaced in place, so they stay SRI
+    match &opts.subcommand {
+        Some(Command::Update(_) | Command::Lock { .. } | Command::Sync { .. }) => {
+            if !matches!(opts.hash_format, HashFormat::Sri) || opts.hash_attr.is_some() {
+                warn!("ignoring --hash-format and --hash-attr, they are not used by this command");
+            }
+        }
+        _ => prefetch::set_hash_output(opts.hash_format.clone(), opts.hash_attr.clone())?,
+    }
+
+    // cached hashes would skip prefetching entirely
+    if opts.dry_run {
+        cache::refresh();
+        prefetch::dry_run();
+    }
+
+    if !opts.options.is_empty() {
+        let mut config = env::var("NIX_CONFIG").unwrap_or_default();
+        if !config.is_empty() && !config.ends_with('\n') {
+            config.push('\n');
+        }
+        for (name, value) in opts.options.iter().tuples() {
+            config.push_str(&format!("{name} = {value}\n"));
+        }
+        env::set_var("NIX_CONFIG", config);
+    }
+
+    if let Some(cacert) = &opts.cacert {
+        let cacert = fs::canonicalize(cacert)
+            .with_context(|| format!("failed to find {}", cacert.display()))?;
+        env::set_var("NIX_SSL_CERT_FILE", &cacert);
+        env::set_var("SSL_CERT_FILE", &cacert);
+        env::set_var("GIT_SSL_CAINFO", &cacert);
+    }
+    if opts.insecure {
+        warn!("TLS certificate verification is disabled, nix will still verify certificates");
+        env::set_var("GIT_SSL_NO_VERIFY", "true");
+    }
+    http::configure_tls(opts.cacert.as_deref(), opts.insecure)?;
+
+    if let Some(expr) = &opts.expr {
+        write!(
+            out,
+            "{}",
+            fod_prefetch(format!(
+                r#"({expr}).overrideAttrs(_:{{outputHash="";outputHashAlgo="sha256";}})"#,
+            ))?
+        )?;
+
+        if out.is_terminal() {
+            writeln!(out)?;
+        }
+
+        return Ok(Outcome::Success);
+    }
+
+    if opts.list_fetchers || opts.list_possible_fetchers {
+        let fetchers = FetcherFunction::value_variants()
+            .iter()
+            .filter(|fetcher| {
+                opts.list_fetchers || !matches!(fetcher, FetcherFunction::BuiltinsFetchGit)
+            })
+            .filter_map(ValueEnum::to_possible_value);
+
+        if let Some(sep) = &opts.list_sep {
+            let mut fetchers = fetchers;
+            if let Some(fetcher) = fetchers.next() {
+                write!(out, "{}", fetcher.get_name())?;
+            }
+            for fetcher in fetchers {
+                write!(out, "{}{}", sep, fetcher.get_name())?;
+            }
+        } else {
+            for fetcher in fetchers {
+                writeln!(out, "{}", fetcher.get_name())?;
+            }
+        }
+
+        return Ok(Outcome::Success);
+    }
+
+    match &opts.subcommand {
+        Some(Command::Update(args)) => {
+            update(out, opts, args)?;
+            return Ok(Outcome::Success);
+        }
+        Some(Command::Outdated {
+            path,
+            recursive,
+            renovate,
+        }) => {
+            return Ok(if outdated(out, opts, path, *recursive, *renovate)? {
+                Outcome::Outdated
+            } else {
+                Outcome::Success
+            });
+        }
+        Some(Command::Watch {
+            target,
+            interval,
+            exec,
+        }) => {
+            watch(out, opts, target, *interval, exec.as_deref())?;
+            return Ok(Outcome::Success);
+        }
+        Some(Command::FromLock { file, input }) => from_lock(out, opts, file, input.as_deref())?,
+        Some(Command::Convert { expr, to }) => convert(out, opts, expr, to.clone())?,
+        Some(Command::Lock { manifest, update }) => lock(opts, manifest, *update)?,
+        Some(Command::Sync { file }) => sync(opts, file)?,
+        None => {}
+    }
+
+    if opts.subcommand.is_some() {
+        if out.is_terminal() {
+            writeln!(out)?;
+        }
+        return Ok(Outcome::Success);
+    }
+
+    if opts.batch {
+        batch(out, opts)?;
+        return Ok(Outcome::Success);
+    }
+
+    if let (true, Some(current)) = (opts.check, &opts.current_rev) {
+        return Ok(if check(out, opts, &opts.url, current)? {
+            Outcome::Outdated
+        } else {
+            Outcome::Success
+        });
+    }
+
+    fetch_url(out, opts, &opts.url, opts.rev.as_deref())?;
+
+    if out.is_terminal() {
+        writeln!(out)?;
+    }
+
+    Ok(Outcome::Success)
+}
+
+// the output of a fetch, JSON is kept as a value so the library API does not have to parse it
+enum Output {
+    Text(Vec<u8>),
+    Json(Value),
+}
+
+fn fetch_url(out: &mut impl Write, opts: &Opts, url: &str, rev: Option<&str>) -> Result<()> {
+    match fetch_output(opts, url, rev)? {
+        Output::Text(text) => out.write_all(&text)?,
+        Output::Json(json) => serde_json::to_writer(out, &json)?,
+    }
+
+    Ok(())
+}
+
+fn fetch_output(opts: &Opts, url: &str, rev: Option<&str>) -> Result<Output> {
+    let url = parse_url(opts, url)?;
+
+    let mut res = fetch(opts, &url, rev, opts.fetcher.clone());
+    for fetcher in &opts.retry_with {
+        let Err(e) = &res else {
+            break;
+        };
+        if e.is::<DryRun>() {
+            break;
+        }
+        warn!("{e:#}");
+        info!("retrying with {fetcher:?}");
+        res = fetch(opts, &url, rev, Some(fetcher.clone()));
+    }
+
+    match res.map_err(|e| e.downcast::<DryRun>()) {
+        Err(Ok(DryRun(prefetched))) => Ok(Output::Text(prefetched.into_bytes())),
+        Err(Err(e)) => Err(e),
+        Ok(output) => Ok(output),
+    }
+}
+
+fn fetch(
+    opts: &Opts,
+    url: &gix_url::Url,
+    rev: Option<&str>,
+    fetcher: Option<FetcherFunction>,
+) -> Result<Output> {
+    // defaults from the instances file only apply to the instance's own fetcher,
+    // not when another one is passed explicitly or retried with
+    let instance = url_host(url)
+        .and_then(|host| find_instance(&host))
+        .filter(|instance| {
+            fetcher
+                .as_ref()
+                .map_or(true, |fetcher| fetcher == &instance.fetcher)
+        });
+
+    with_fetcher(opts, fetcher, url, |fetcher, url| {
+        fetch_with(opts, fetcher, url, rev, instance)
+    })
+}
+
+fn fetch_with<'a>(
+    opts: &Opts,
+    fetcher: &'a FetcherDispatch<'a>,
+    url: &'a Url<'a>,
+    rev: Option<&str>,
+    instance: Option<&Instance>,
+) -> Result<Output> {
+    if opts.private {
+        if !matches!(fetcher, FetcherDispatch::FetchFromGitHub(_)) {
+            bail!("--private is only supported by fetchFromGitHub");
+        }
+
+        warn!(
+            "building the generated expression requires NIX_GITHUB_PRIVATE_USERNAME \
+            and NIX_GITHUB_PRIVATE_PASSWORD to be set in the environment of the nix daemon",
+        );
+    }
+
+    if !opts.sparse.is_empty()
+        && !matches!(
+            fetcher,
+            FetcherDispatch::Fetchgit(_)
+                | FetcherDispatch::FetchFromGitHub(_)
+                | FetcherDispatch::FetchFromGitLab(_)
+                | FetcherDispatch::FetchFromGitea(_),
+        )
+    {
+        bail!("--sparse is only supported by fetchgit, fetchFromGitHub, fetchFromGitLab, and fetchFromGitea");
+    }
+
+    if matches!(opts.hash_attr, Some(HashAttr::Hash))
+        && matches!(
+            fetcher,
+            FetcherDispatch::FetchHex(_)
+                | FetcherDispatch::FetchRepoProject(_)
+                | FetcherDispatch::Fetchbzr(_)
+                | FetcherDispatch::Fetchdarcs(_)
+                | FetcherDispatch::Fetchfossil(_)
+                | FetcherDispatch::Fetchhg(_)
+                | FetcherDispatch::Fetchsvn(_),
+        )
+    {
+        bail!("--hash-attr hash is not supported by fetchers that only accept sha256");
+    }
+
+    if opts.verify_signatures.is_some()
+        && !matches!(
+            fetcher,
+            FetcherDispatch::BuiltinsFetchGit(_)
+                | FetcherDispatch::FetchFromBitbucket(_)
+                | FetcherDispatch::FetchFromGitHub(_)
+                | FetcherDispatch::FetchFromGitLab(_)
+                | FetcherDispatch::FetchFromGitea(_)
+                | FetcherDispatch::FetchFromGitiles(_)
+                | FetcherDispatch::FetchFromRepoOrCz(_)
+                | FetcherDispatch::FetchFromSourcehut(_)
+                | FetcherDispatch::Fetchgit(_),
+        )
+    {
+        bail!("--verify-signatures is only supported by git repositories");
+    }
+
+    if opts.meta
+        && !matches!(
+            fetcher,
+            FetcherDispatch::FetchFromGitHub(_)
+                | FetcherDispatch::FetchFromGitLab(_)
+                | FetcherDispatch::FetchFromGitea(_),
+        )
+    {
+        bail!("--meta is only supported by fetchFromGitHub, fetchFromGitLab, and fetchFromGitea");
+    }
+
+    if opts.manifest_name.is_some() && !matches!(fetcher, FetcherDispatch::FetchRepoProject(_)) {
+        bail!("--manifest-name is only supported by fetchRepoProject");
+    }
+
+    // fetchHex always downloads from the public repository, only the hash is valid elsewhere
+    if let FetcherDispatch::FetchHex(hex) = fetcher {
+        if !opts.hash && !hex.is_public(url) {
+            bail!(
+                "fetchHex only supports public packages on repo.hex.pm, \
+                pass --hash to only output the hash of {url}",
+            );
+        }
+    }
+
+    let rev = match rev {
+        Some(rev) => Some(rev.into()),
+        None if opts.tag => Some(latest_tag(opts, fetcher, url)?),
+        None => None,
+    };
+
+    let version = match &opts.rev_template {
+        Some(_)
+            if matches!(
+                fetcher,
+                FetcherDispatch::BuiltinsFetchGit(_)
+                    | FetcherDispatch::FetchCrate(_)
+                    | FetcherDispatch::FetchHex(_)
+                    | FetcherDispatch::FetchPypi(_)
+                    | FetcherDispatch::Fetchurl(_),
+            ) =>
+        {
+            bail!("--rev-template is not supported by fetchers without a rev argument");
+        }
+        Some(template) => {
+            let rev = rev
+                .as_deref()
+                .context("--rev-template requires a tag, pass one or use --tag")?;
+            Some(template_version(template, rev)?)
+        }
+        None => None,
+    };
+
+    let meta = opts.meta.then(|| {
+        let tag = rev
+            .as_deref()
+            .and_then(|rev| release_tag(url.as_str(), rev));
+        fetcher.meta(url, tag)
+    });
+    if meta.as_ref().is_some_and(|meta| meta.licenses.is_empty()) {
+        warn!("failed to find the license of {url}");
+    }
+
+    // the verified commit is pinned, since the tag or branch could be moved afterwards
+    let rev = if let Some(keyring) = &opts.verify_signatures {
+        let rev = match rev {
+            Some(rev) => rev,
+            None => fetcher.latest_rev(url)?,
+        };
+        let git_url = url.as_str();
+        let commit = git_verify_signature(
+            git_url.strip_prefix("git+").unwrap_or(git_url),
+            &rev,
+            keyring,
+        )?;
+        info!("verified the signature of {rev} at {commit}");
+        Some(commit)
+    } else {
+        rev
+    };
+
+    let mut args: Vec<_> = opts.args.iter().cloned().tuples().collect();
+    if !opts.sparse.is_empty() {
+        args.push((
+            "sparseCheckout".into(),
+            format!(
+                "[ {} ]",
+                opts.sparse
+                    .iter()
+                    .map(|path| format!(r#""{}""#, nix_escape(path)))
+                    .join(" "),
+            ),
+        ));
+    }
+
+    let args_json = opts
+        .args_json
+        .iter()
+        .tuples()
+        .map(|(key, json)| {
+            let value: Value = serde_json::from_str(json)
+                .with_context(|| format!("failed to parse the JSON of --arg-json {key}"))?;
+            Ok((key.clone(), value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let nix_indent = format!("{}  ", " ".repeat(opts.indent));
+    for (key, value) in &args_json {
+        args.push((key.clone(), nix_value(value, &nix_indent)));
+    }
+
+    let mut args_str: Vec<_> = opts.args_str.iter().cloned().tuples().collect();
+    if let Some(name) = &opts.manifest_name {
+        args_str.push(("manifestName".into(), name.clone()));
+    }
+
+    // defaults from the instances file apply unless the argument is passed explicitly
+    let submodules = opts
+        .submodules
+        .or(instance.and_then(|instance| instance.submodules));
+    if let Some(instance) = instance {
+        for (key, value) in &instance.args {
+            if !args.iter().chain(&args_str).any(|(k, _)| k == key) {
+                args.push((key.clone(), value.clone()));
+            }
+        }
+    }
+
+    if opts.parse {
+        return fetcher.to_json(url, rev).map(Output::Json);
+    }
+
+    // every output is written from a single prefetch, so the source is never fetched twice
+    let overwrites: Vec<(String, String)> = opts.overwrites.iter().cloned().tuples().collect();
+    let prefetched = fetcher.prefetch(
+        url,
+        rev,
+        opts.pin,
+        !opts.no_cache,
+        opts.hash,
+        submodules,
+        &overwrites,
+        &args,
+        args_str,
+        opts.nixpkgs.clone(),
+    )?;
+
+    // the fetcher call without overwrites, which the other outputs are derived from
+    let json = if opts.cross_check
+        || opts.gha
+        || opts.lang.is_some()
+        || opts.sbom.is_some()
+        || opts.flake.is_some()
+    {
+        fetcher.prefetched_json(
+            url,
+            prefetched.clone(),
+            args.clone(),
+            Vec::new(),
+            Vec::new(),
+        )?
+    } else {
+        Value::Null
+    };
+
+    if opts.cross_check {
+        // the output would not contain the hash that is compared
+        if overwrites
+            .iter()
+            .map(|(key, _)| key)
+            .chain(opts.overwrites_str.iter().step_by(2))
+            .any(|key| key == "hash" || key == "sha256")
+        {
+            bail!("--cross-check can not verify an overwritten hash");
+        }
+        cross_check(&json, &prefetched.hash)?;
+    }
+    if opts.gha {
+        gha::set_outputs(&json)?;
+    }
+    let dependency = opts
+        .lang
+        .as_ref()
+        .map(|lang| dependency_hash(lang, &json, &opts.nixpkgs, !opts.no_cache))
+        .transpose()?;
+
+    let mut out = Vec::new();
+    if opts.hash {
+        write!(out, "{}", format_hash(&prefetched.hash)?)?;
+    } else if opts.json {
+        validate_overwrites(&opts.overwrites)?;
+        let mut overwrites_str: Vec<(String, String)> =
+            opts.overwrites_str.iter().cloned().tuples().collect();
+        if let Some(template) = &opts.rev_template {
+            if !overwrites
+                .iter()
+                .chain(&overwrites_str)
+                .any(|(key, _)| key == "rev")
+            {
+                overwrites_str.push(("rev".into(), template.clone()));
+            }
+        }
+
+        // arguments from --arg-json are kept as JSON unless they are overwritten
+        let args_json: Vec<_> = args_json
+            .into_iter()
+            .filter(|(key, _)| {
+                !overwrites
+                    .iter()
+                    .chain(&overwrites_str)
+                    .any(|(k, _)| k == key)
+            })
+            .collect();
+
+        let mut json =
+            fetcher.prefetched_json(url, prefetched, args, overwrites, overwrites_str)?;
+        for (key, value) in args_json {
+            json["args"][key] = value;
+        }
+        if let Some(version) = version {
+            json["version"] = Value::from(version);
+        }
+        // meta belongs to the package rather than the fetcher call
+        if let Some(meta) = &meta {
+            json["meta"] = Value::from_iter(
+                license_expr(&meta.licenses, false)
+                    .map(|license| ("license", license))
+                    .into_iter()
+                    .chain(meta.strings()),
+            );
+        }
+        if let Some((key, hash)) = dependency {
+            json[key] = Value::from(hash);
+        }
+
+        return Ok(Output::Json(json));
+    } else if let Some(format) = &opts.sbom {
+        write_sbom(&mut out, format, url.as_str(), &json)?;
+    } else if let Some(format) = &opts.flake {
+        write_flake(&mut out, format, url.as_str(), &json)?;
+    } else {
+        validate_overwrites(&opts.overwrites)?;
+        let mut overwrites: IndexMap<_, _> = overwrites.into_iter().collect();
+
+        for (key, value) in opts.overwrites_str.iter().cloned().tuples() {
+            overwrites.insert(key, format!(r#""{value}""#));
+        }
+        if let Some(template) = &opts.rev_template {
+            overwrites
+                .entry("rev".into())
+                .or_insert_with(|| format!(r#""{template}""#));
+        }
+
+        let verify_prefix = opts.verify.then(|| {
+            if matches!(fetcher, FetcherDispatch::BuiltinsFetchGit(_)) {
+                String::new()
+            } else {
+                format!("(import({}){{}}).", opts.nixpkgs)
+            }
+        });
+
+        // the version, meta, and dependency hash belong to the package rather than the fetcher
+        // call, they are output in an attribute set with the fetcher call as src
+        let wrap = version.is_some() || meta.is_some() || dependency.is_some();
+        let indent = " ".repeat(opts.indent);
+
+        let mut expr = Vec::new();
+        fetcher.write_prefetched_nix(
+            &mut expr,
+            url,
+            prefetched,
+            args,
+            overwrites,
+            if wrap {
+                format!("{indent}  ")
+            } else {
+                indent.clone()
+            },
+        )?;
+
+        if let Some(prefix) = verify_prefix {
+            let bindings = version.map_or_else(String::new, |version| {
+                format!(r#"let version = "{}"; in "#, nix_escape(version))
+            });
+            verify(format!("{bindings}{prefix}{}", expr.to_str()?))?;
+        }
+
+        if wrap {
+            // the rev refers to version, which is only in scope of a recursive attribute set
+            writeln!(out, "{}{{", if version.is_some() { "rec " } else { "" })?;
+            if let Some(version) = version {
+                writeln!(out, r#"{indent}  version = "{}";"#, nix_escape(version))?;
+            }
+            write!(out, "{indent}  src = ")?;
+            out.write_all(&expr)?;
+            writeln!(out, ";")?;
+            if let Some((key, hash)) = dependency {
+                writeln!(out, r#"{indent}  {key} = "{hash}";"#)?;
+            }
+            if let Some(meta) = &meta {
+                if let Some(license) = license_expr(&meta.licenses, true) {
+                    writeln!(out, "{indent}  meta.license = {license};")?;
+                }
+                for (key, value) in meta.strings() {
+                    writeln!(out, r#"{indent}  meta.{key} = "{}";"#, nix_escape(&value))?;
+                }
+            }
+            write!(out, "{indent}}}")?;
+        } else {
+            out.write_all(&expr)?;
+        }
+    }
+
+    Ok(Output::Text(out))
+}
+
+fn latest_tag<'a>(opts: &Opts, fetcher: &FetcherDispatch<'a>, url: &'a Url) -> Result<String> {
+    let filter = TagFilter {
+        glob: opts
+            .tag_filter
+            .as_deref()
+            .map(Pattern::new)
+            .transpose()
+            .context("failed to parse --tag-filter")?,
+        semver: opts
+            .tag_semver
+            .as_deref()
+            .map(VersionReq::parse)
+            .transpose()
+            .context("failed to parse --tag-semver")?,
+    };
+    let tag = fetcher.latest_tag(url, &filter)?;
+    info!("the latest tag of {url} is {tag}");
+    Ok(tag)
+}
+
+// the part of the tag that ${version} stands for in the template, e.g. 1.0.0 from v1.0.0 and v${version}
+fn template_version<'a>(template: &str, rev: &'a str) -> Result<&'a str> {
+    let Some((prefix, suffix)) = template.split_once("${version}") else {
+        bail!("--rev-template must contain ${{version}}");
+    };
+
+    let tag = rev.strip_prefix("refs/tags/").unwrap_or(rev);
+    match tag
+        .strip_prefix(prefix)
+        .and_then(|tag| tag.strip_suffix(suffix))
+    {
+        Some(version) if !version.is_empty() => Ok(version),
+        _ => bail!("{tag} does not match the template {template}"),
+    }
+}
+
+// returns the tag if the revision is one, release pages are only linked for tags
+fn release_tag<'a>(url: &str, rev: &'a str) -> Option<&'a str> {
+    let tag = rev.strip_prefix("refs/tags/").unwrap_or(rev);
+    if rev.len() == 40 && rev.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let refs = git_ls_remote(url.strip_prefix("git+").unwrap_or(url)).ok()?;
+    refs.lines()
+        .any(|line| {
+            line.split('\t')
+                .nth(1)
+                .and_then(|name| name.strip_prefix("refs/tags/"))
+                == Some(tag)
+        })
+        .then_some(tag)
+}
+
+fn parse_url(opts: &Opts, url: &str) -> Result<gix_url::Url> {
+    let mut url: gix_url::Url = url.try_into()?;
+
+    if opts.fetcher.is_none() && url.host() == Some("sr.ht") {
+        let host = find_sourcehut_host(url.path.to_str()?)?;
+        url = gix_url::Url::from_parts(
+            url.scheme.clone(),
+            url.user().map(Into::into),
+            url.password().map(Into::into),
+            Some(host.into()),
+            url.port,
+            url.path.clone(),
+            false,
+        )?;
+    }
+
+    if opts.fetcher.is_none() && matches!(url.scheme, Scheme::Http | Scheme::Https) {
+        if let Some(redirected) = follow_redirects(&url) {
+            url = redirected;
+        }
+    }
+
+    // fetchbzr needs the branch itself instead of its page on launchpad,
+    // e.g. https://bazaar.launchpad.net/+branch/project for https://launchpad.net/project
+    if matches!(opts.fetcher, None | Some(FetcherFunction::Fetchbzr))
+        && matches!(url.host(), Some("code.launchpad.net" | "launchpad.net"))
+        && !url.path.contains_str("/+git/")
+    {
+        let path = if url.path.starts_with(b"/~") {
+            url.path.clone()
+        } else {
+            format!("/+branch{}", url.path).into()
+        };
+        url = gix_url::Url::from_parts(
+            Scheme::Https,
+            None,
+            None,
+            Some("bazaar.launchpad.net".into()),
+            None,
+            path,
+            false,
+        )?;
+    }
+
+    Ok(url)
+}
+
+fn validate_overwrites(overwrites: &[String]) -> Result<()> {
+    for (name, expr) in overwrites.iter().tuples() {
+        nix_parse(expr)
+            .with_context(|| format!("invalid Nix expression for --overwrite {name}"))?;
+    }
+
+    Ok(())
+}
+
+fn is_known_host(host: &str) -> bool {
+    matches!(
+        host,
+        "bazaar.launchpad.net"
+            | "bitbucket.org"
+            | "chiselapp.com"
+            | "code.launchpad.net"
+            | "crates.io"
+            | "git.sr.ht"
+            | "github.com"
+            | "gitlab.com"
+            | "hex.pm"
+            | "hg.sr.ht"
+            | "hub.darcs.net"
+            | "launchpad.net"
+            | "lib.rs"
+            | "pypi.org"
+            | "repo.or.cz"
+    ) || host.starts_with("gitlab.")
+        || find_instance(host).is_some()
+        || host.ends_with(".googlesource.com")
+}
+
+// follow a bounded number of redirects so vanity domains can be classified by the forge they point to
+fn follow_redirects(url: &gix_url::Url) -> Option<gix_url::Url> {
+    if is_known_host(url.host()?) {
+        return None;
+    }
+
+    let url = url.to_bstring();
+    let url = url.to_str().ok()?;
+    let resp = match http::head(url).call() {
+        Ok(resp) | Err(ureq::Error::Status(_, resp)) => resp,
+        Err(_) => return None,
+    };
+
+    let target: gix_url::Url = resp.get_url().try_into().ok()?;
+    if !target.host().is_some_and(is_known_host) {
+        return None;
+    }
+
+    info!("{url} redirects to {}", resp.get_url());
+    Some(target)
+}
+
+// the host including the port, which tells self-hosted instances apart
+fn url_host(url: &gix_url::Url) -> Option<String> {
+    match (url.host(), url.port) {
+        (Some(host), Some(port)) => Some(format!("{host}:{port}")),
+        (host, _) => host.map(Into::into),
+    }
+}
+
+// resolves the fetcher of the URL and passes it the URL in the form that fetchers take
+fn with_fetcher<T>(
+    opts: &Opts,
+    fetcher: Option<FetcherFunction>,
+    url: &gix_url::Url,
+    f: impl for<'a> FnOnce(&'a FetcherDispatch<'a>, &'a Url<'a>) -> Result<T>,
+) -> Result<T> {
+    let host = url_host(url);
+    let fetcher = get_fetcher(opts, fetcher, url, host.as_deref())?;
+
+    let url_bstring = url.to_bstring();
+    let path = url.path.to_str()?;
+    let url = Url {
+        url: url_bstring.to_str()?,
+        path: path.strip_prefix('/').unwrap_or(path),
+    };
+
+    f(&fetcher, &url)
+}
+
+fn get_fetcher<'a>(
+    opts: &'a Opts,
+    fetcher: Option<FetcherFunction>,
+    url: &gix_url::Url,
+    host: Option<&'a str>,
+) -> Result<FetcherDispatch<'a>> {
+    let pypi_wheel = matches!(opts.pypi_format, PypiFormat::Wheel).then(|| WheelTags {
+        python: opts.pypi_python.as_deref(),
+        abi: opts.pypi_abi.as_deref(),
+        platform: opts.pypi_platform.as_deref(),
+    });
+
+    // known instances are handled as if their fetcher was specified
+    let fetcher = fetcher.or_else(|| Some(host.and_then(find_instance)?.fetcher.clone()));
+
+    Ok(match (fetcher, host, &url.scheme) {
+        (Some(FetcherFunction::BuiltinsFetchGit), ..) => {
+            BuiltinsFetchGit(opts.branch.as_deref()).into()
+        }
+
+        (None | Some(FetcherFunction::FetchCrate), Some("crates.io"), _) => {
+            FetchCrate::new(Registry::CratesIo, !opts.no_trust_upstream_hash).into()
+        }
+        (None | Some(FetcherFunction::FetchCrate), Some("lib.rs"), _) => {
+            FetchCrate::new(Registry::LibRs, !opts.no_trust_upstream_hash).into()
+        }
+        (None | Some(FetcherFunction::FetchCrate), _, Scheme::Ext(scheme))
+            if scheme.starts_with("sparse+") =>
+        {
+            FetchCrate::new(Registry::custom(url)?, !opts.no_trust_upstream_hash).into()
+        }
+        (Some(FetcherFunction::FetchCrate), ..) => {
+            bail!("fetchCrate only supports crates.io, lib.rs, and sparse registries");
+        }
+
+        (None | Some(FetcherFunction::FetchFromBitbucket), Some("bitbucket.org"), _) => {
+            FetchFromBitbucket.into()
+        }
+        (Some(FetcherFunction::FetchFromBitbucket), ..) => {
+            bail!("fetchFromBitbucket only supports bitbucket.org");
+        }
+
+        (None | Some(FetcherFunction::FetchFromGitHub), Some("github.com"), _) => {
+            FetchFromGitHub::new(None, opts.private, opts.wait).into()
+        }
+        (Some(FetcherFunction::FetchFromGitHub), Some(host), _) => {
+            FetchFromGitHub::new(Some(host), opts.private, opts.wait).into()
+        }
+
+        (None | Some(FetcherFunction::FetchFromGitLab), Some("gitlab.com"), _) => {
+            FetchFromGitLab::new(None).into()
+        }
+        (None, Some(host), _) if host.starts_with("gitlab.") => {
+            FetchFromGitLab::new(Some(host)).into()
+        }
+        (Some(FetcherFunction::FetchFromGitLab), Some(host), _) => {
+            FetchFromGitLab::new(Some(host)).into()
+        }
+
+        (Some(FetcherFunction::FetchFromGitea), Some(host), _) => FetchFromGitea::new(host).into(),
+
+        (None | Some(FetcherFunction::FetchFromGitiles), Some(host), _)
+            if host.ends_with(".googlesource.com") =>
+        {
+            FetchFromGitiles.into()
+        }
+        (Some(FetcherFunction::FetchFromGitiles), ..) => FetchFromGitiles.into(),
+
+        (None | Some(FetcherFunction::FetchFromRepoOrCz), Some("repo.or.cz"), _) => {
+            FetchFromRepoOrCz.into()
+        }
+        (Some(FetcherFunction::FetchFromRepoOrCz), ..) => {
+            bail!("fetchFromRepoOrCz only supports repo.or.cz");
+        }
+
+        (None | Some(FetcherFunction::FetchFromSourcehut), Some("git.sr.ht"), _) => {
+            FetchFromSourcehut(None).into()
+        }
+        (Some(FetcherFunction::FetchFromSourcehut), Some(host), _) => {
+            FetchFromSourcehut(Some(host)).into()
+        }
+
+        (
+            Some(
+                fetcher @ (FetcherFunction::FetchFromGitHub
+                | FetcherFunction::FetchFromGitLab
+                | FetcherFunction::FetchFromGitea
+                | FetcherFunction::FetchFromSourcehut
+                | FetcherFunction::FetchHex),
+            ),
+            None,
+            _,
+        ) => {
+            bail!("{fetcher:?} does not support URLs without a host");
+        }
+
+        (None | Some(FetcherFunction::FetchHex), Some("hex.pm"), _) => {
+            FetchHex::new(None, !opts.no_trust_upstream_hash).into()
+        }
+        (Some(FetcherFunction::FetchHex), Some(host), _) => {
+            FetchHex::new(Some(host), !opts.no_trust_upstream_hash).into()
+        }
+
+        (None | Some(FetcherFunction::FetchPypi), Some("pypi.org"), _) => FetchPypi {
+            wheel: pypi_wheel,
+            trust_upstream_hash: !opts.no_trust_upstream_hash,
+        }
+        .into(),
+        (Some(FetcherFunction::FetchPypi), ..) => {
+            bail!("fetchPypi only supports pypi.org");
+        }
+
+        (Some(FetcherFunction::FetchRepoProject), ..) => FetchRepoProject.into(),
+
+        (None, Some("bazaar.launchpad.net"), _) => Fetchbzr.into(),
+        (None, _, Scheme::Ext(scheme)) if scheme == "bzr" || scheme.starts_with("bzr+") => {
+            Fetchbzr.into()
+        }
+        (Some(FetcherFunction::Fetchbzr), ..) => Fetchbzr.into(),
+
+        (None, Some("hub.darcs.net"), _) => Fetchdarcs.into(),
+        (Some(FetcherFunction::Fetchdarcs), ..) => Fetchdarcs.into(),
+
+        (None, Some("chiselapp.com"), _) => Fetchfossil.into(),
+        (None, ..) if url.path.ends_with(b".fossil") => Fetchfossil.into(),
+        (Some(FetcherFunction::Fetchfossil), ..) => Fetchfossil.into(),
+
+        (None | Some(FetcherFunction::Fetchgit), _, Scheme::Git) => Fetchgit(GitScheme::Yes).into(),
+        (None | Some(FetcherFunction::Fetchgit), _, Scheme::Ext(scheme))
+            if scheme.starts_with("git+") =>
+        {
+            Fetchgit(GitScheme::Plus).into()
+        }
+        (Some(FetcherFunction::Fetchgit), ..) => Fetchgit(GitScheme::No).into(),
+
+        (None, Some("hg.sr.ht"), _) => Fetchhg::new(false).into(),
+        (None | Some(FetcherFunction::Fetchhg), _, Scheme::Ext(scheme))
+            if scheme.starts_with("hg+") =>
+        {
+            Fetchhg::new(true).into()
+        }
+        (Some(FetcherFunction::Fetchhg), ..) => Fetchhg::new(false).into(),
+
+        (None, _, Scheme::Ext(scheme)) if scheme == "svn" => Fetchsvn.into(),
+        (Some(FetcherFunction::Fetchsvn), ..) => Fetchsvn.into(),
+
+        (
+            None,
+            Some(
+                "archive.apache.org"
+                | "dlcdn.apache.org"
+                | "downloads.apache.org"
+                | "downloads.sourceforge.net"
+                | "ftp.gnu.org"
+                | "ftpmirror.gnu.org"
+                | "prdownloads.sourceforge.net"
+                | "www.apache.org",
+            ),
+            _,
+        ) => Fetchurl.into(),
+        (Some(FetcherFunction::Fetchurl), ..) => Fetchurl.into(),
+
+        (None, ..) => match &opts.fallback {
+            FetcherFunction::BuiltinsFetchGit => BuiltinsFetchGit(opts.branch.as_deref()).into(),
+            FetcherFunction::FetchCrate => {
+                bail!("fetchCrate only supports crates.io, lib.rs, and sparse registries");
+            }
+            FetcherFunction::FetchFromBitbucket => {
+                bail!("fetchFromBitbucket only supports bitbucket.org");
+            }
+            fetcher @ (FetcherFunction::FetchFromGitHub
+            | FetcherFunction::FetchFromGitLab
+            | FetcherFunction::FetchFromGitea
+            | FetcherFunction::FetchFromSourcehut
+            | FetcherFunction::FetchHex) => {
+                bail!("{fetcher:?} does not support URLs without a host");
+            }
+            FetcherFunction::FetchFromGitiles => FetchFromGitiles.into(),
+            FetcherFunction::FetchFromRepoOrCz => {
+                bail!("fetchFromRepoOrCz only supports repo.or.cz");
+            }
+            FetcherFunction::FetchPypi => {
+                bail!("fetchPypi only supports pypi.org");
+            }
+            FetcherFunction::FetchRepoProject => FetchRepoProject.into(),
+            FetcherFunction::Fetchbzr => Fetchbzr.into(),
+            FetcherFunction::Fetchdarcs => Fetchdarcs.into(),
+            FetcherFunction::Fetchfossil => Fetchfossil.into(),
+            FetcherFunction::Fetchgit => Fetchgit(GitScheme::No).into(),
+            FetcherFunction::Fetchhg => Fetchhg::new(false).into(),
+            FetcherFunction::Fetchsvn => Fetchsvn.into(),
+            FetcherFunction::Fetchurl => Fetchurl.into(),
+        },
+    })
+}
@@ -0,0 +1,89 @@
+use itertools::Itertools;
+
+use crate::warn;
+
+// maps SPDX identifiers to attributes of lib.licenses,
+// the second element is the alternative for identifiers that do not say whether later versions apply
+fn nixpkgs_license(spdx: &str) -> Option<(&'static str, Option<&'static str>)> {
+    Some(match spdx.to_ascii_lowercase().as_str() {
+        "0bsd" => ("bsd0", None),
+        "afl-3.0" => ("afl3", None),
+        "agpl-3.0" => ("agpl3Only", Some("agpl3Plus")),
+        "agpl-3.0-only" => ("agpl3Only", None),
+        "agpl-3.0-or-later" => ("agpl3Plus", None),
+        "apache-2.0" => ("asl20", None),
+        "artistic-2.0" => ("artistic2", None),
+        "bsd-2-clause" => ("bsd2", None),
+        "bsd-3-clause" => ("bsd3", None),
+        "bsd-3-clause-clear" => ("bsd3Clear", None),
+        "bsl-1.0" => ("boost", None),
+        "cc-by-4.0" => ("cc-by-40", None),
+        "cc-by-sa-4.0" => ("cc-by-sa-40", None),
+        "cc0-1.0" => ("cc0", None),
+        "epl-1.0" => ("epl10", None),
+        "epl-2.0" => ("epl20", None),
+        "eupl-1.2" => ("eupl12", None),
+        "gpl-2.0" => ("gpl2Only", Some("gpl2Plus")),
+        "gpl-2.0-only" => ("gpl2Only", None),
+        "gpl-2.0-or-later" => ("gpl2Plus", None),
+        "gpl-3.0" => ("gpl3Only", Some("gpl3Plus")),
+        "gpl-3.0-only" => ("gpl3Only", None),
+        "gpl-3.0-or-later" => ("gpl3Plus", None),
+        "isc" => ("isc", None),
+        "lgpl-2.1" => ("lgpl21Only", Some("lgpl21Plus")),
+        "lgpl-2.1-only" => ("lgpl21Only", None),
+        "lgpl-2.1-or-later" => ("lgpl21Plus", None),
+        "lgpl-3.0" => ("lgpl3Only", Some("lgpl3Plus")),
+        "lgpl-3.0-only" => ("lgpl3Only", None),
+        "lgpl-3.0-or-later" => ("lgpl3Plus", None),
+        "mit" => ("mit", None),
+        "mit-0" => ("mit0", None),
+        "mpl-2.0" => ("mpl20", None),
+        "ms-pl" => ("mspl", None),
+        "ofl-1.1" => ("ofl", None),
+        "postgresql" => ("postgresql", None),
+        "unlicense" => ("unlicense", None),
+        "upl-1.0" => ("upl", None),
+        "vim" => ("vim", None),
+        "wtfpl" => ("wtfpl", None),
+        "zlib" => ("zlib", None),
+        _ => return None,
+    })
+}
+
+// returns the expression for meta.license,
+// ambiguous licenses are followed by a comment with their alternatives if requested
+pub fn license_expr(licenses: &[String], comment: bool) -> Option<String> {
+    let mut ambiguous = Vec::new();
+    let attrs: Vec<_> = licenses
+        .iter()
+        .filter_map(|license| match nixpkgs_license(license) {
+            Some((attr, alternative)) => {
+                if let Some(alternative) = alternative {
+                    warn!(
+                        "{license} does not specify whether later versions apply, \
+                        using {attr} instead of {alternative}",
+                    );
+                    ambiguous.push(alternative);
+                }
+                Some(attr)
+            }
+            None => {
+                warn!("{license} has no known equivalent in lib.licenses");
+                None
+            }
+        })
+        .collect();
+
+    let expr = match attrs.as_slice() {
+        [] => return None,
+        [attr] => format!("lib.licenses.{attr}"),
+        attrs => format!("with lib.licenses; [ {} ]", attrs.iter().join(" ")),
+    };
+
+    Some(if comment && !ambiguous.is_empty() {
+        format!("{expr} /* or {} */", ambiguous.iter().join(", "))
+    } else {
+        expr
+    })
+}
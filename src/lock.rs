@@ -0,0 +1,251 @@
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+
+use anyhow::{bail, Context, Result};
+use bstr::ByteSlice;
+use serde::Deserialize;
+
+use crate::{
+    cli::{FetcherFunction, Opts},
+    fetcher::Fetcher,
+    prefetch::{format_hash, hash_attr},
+    simple::nix_escape,
+    warn, with_fetcher,
+};
+
+#[derive(Deserialize)]
+struct Lock {
+    nodes: BTreeMap<String, Node>,
+    root: String,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    #[serde(default)]
+    inputs: BTreeMap<String, Input>,
+    locked: Option<Locked>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Input {
+    Node(String),
+    Follows(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Locked {
+    Github {
+        host: Option<String>,
+        owner: String,
+        repo: String,
+        rev: String,
+        #[serde(rename = "narHash")]
+        nar_hash: Option<String>,
+    },
+    Gitlab {
+        host: Option<String>,
+        owner: String,
+        repo: String,
+        rev: String,
+        #[serde(rename = "narHash")]
+        nar_hash: Option<String>,
+    },
+    Sourcehut {
+        host: Option<String>,
+        owner: String,
+        repo: String,
+        rev: String,
+        #[serde(rename = "narHash")]
+        nar_hash: Option<String>,
+    },
+    Git {
+        url: String,
+        rev: String,
+        #[serde(default)]
+        submodules: bool,
+        #[serde(rename = "narHash")]
+        nar_hash: Option<String>,
+    },
+    Mercurial {
+        url: String,
+        rev: String,
+        #[serde(rename = "narHash")]
+        nar_hash: Option<String>,
+    },
+    Tarball {
+        url: String,
+        #[serde(rename = "narHash")]
+        nar_hash: String,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+pub fn from_lock(
+    out: &mut impl Write,
+    opts: &Opts,
+    path: &Path,
+    input: Option<&str>,
+) -> Result<()> {
+    let lock = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut lock: Lock = serde_json::from_slice(&lock)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+
+    let root = lock
+        .nodes
+        .remove(&lock.root)
+        .context("failed to find the root node")?;
+
+    if let Some(input) = input {
+        let node = match root.inputs.get(input) {
+            Some(Input::Node(node)) => node,
+            Some(Input::Follows(_)) => {
+                bail!("{input} follows another input, use that input instead");
+            }
+            None => bail!("{input} is not an input of the flake"),
+        };
+        let locked = lock
+            .nodes
+            .get(node)
+            .and_then(|node| node.locked.as_ref())
+            .with_context(|| format!("{input} is not locked"))?;
+
+        return write_locked(out, opts, locked, " ".repeat(opts.indent));
+    }
+
+    let indent = " ".repeat(opts.indent);
+    writeln!(out, "{{")?;
+    for (name, node) in &root.inputs {
+        // inputs following other inputs are the same source
+        let Input::Node(node) = node else {
+            continue;
+        };
+        let Some(locked) = lock.nodes.get(node).and_then(|node| node.locked.as_ref()) else {
+            warn!("skipping {name}: the input is not locked");
+            continue;
+        };
+
+        let mut expr = Vec::new();
+        if let Err(e) = write_locked(&mut expr, opts, locked, format!("{indent}  ")) {
+            warn!("skipping {name}: {e:#}");
+            continue;
+        }
+        writeln!(out, "{indent}  {name} = {};", expr.to_str()?)?;
+    }
+    write!(out, "{indent}}}")?;
+
+    Ok(())
+}
+
+fn write_locked(out: &mut impl Write, opts: &Opts, locked: &Locked, indent: String) -> Result<()> {
+    let (fetcher, url, rev, submodules, nar_hash) = match locked {
+        Locked::Github {
+            host,
+            owner,
+            repo,
+            rev,
+            nar_hash,
+        } => (
+            FetcherFunction::FetchFromGitHub,
+            format!(
+                "https://{}/{owner}/{repo}",
+                host.as_deref().unwrap_or("github.com")
+            ),
+            rev,
+            false,
+            nar_hash,
+        ),
+        Locked::Gitlab {
+            host,
+            owner,
+            repo,
+            rev,
+            nar_hash,
+        } => (
+            FetcherFunction::FetchFromGitLab,
+            format!(
+                "https://{}/{}/{repo}",
+                host.as_deref().unwrap_or("gitlab.com"),
+                owner.replace("%2F", "/"),
+            ),
+            rev,
+            false,
+            nar_hash,
+        ),
+        Locked::Sourcehut {
+            host,
+            owner,
+            repo,
+            rev,
+            nar_hash,
+        } => (
+            FetcherFunction::FetchFromSourcehut,
+            format!(
+                "https://{}/{owner}/{repo}",
+                host.as_deref().unwrap_or("git.sr.ht")
+            ),
+            rev,
+            false,
+            nar_hash,
+        ),
+        Locked::Git {
+            url,
+            rev,
+            submodules,
+            nar_hash,
+        } => (
+            FetcherFunction::Fetchgit,
+            url.clone(),
+            rev,
+            *submodules,
+            nar_hash,
+        ),
+        Locked::Mercurial { url, rev, nar_hash } => {
+            (FetcherFunction::Fetchhg, url.clone(), rev, false, nar_hash)
+        }
+        Locked::Tarball { url, nar_hash } => {
+            // the NAR hash of an unpacked tarball is exactly what fetchzip expects
+            writeln!(out, "fetchzip {{")?;
+            writeln!(out, r#"{indent}  url = "{}";"#, nix_escape(url))?;
+            writeln!(
+                out,
+                r#"{indent}  {} = "{}";"#,
+                hash_attr("hash"),
+                format_hash(nar_hash)?,
+            )?;
+            write!(out, "{indent}}}")?;
+            return Ok(());
+        }
+        Locked::Unsupported => bail!("the input type is not supported"),
+    };
+
+    let url: gix_url::Url = url.as_str().try_into()?;
+    with_fetcher(opts, Some(fetcher), &url, |fetcher, url| {
+        // the lock already records the hash of the source, only prefetch when it is missing
+        if let Some(nar_hash) = nar_hash {
+            return fetcher.write_known(
+                out,
+                url,
+                rev.clone(),
+                nar_hash.clone(),
+                Some(submodules),
+                indent,
+            );
+        }
+
+        fetcher.fetch_nix(
+            out,
+            url,
+            Some(rev.clone()),
+            false,
+            !opts.no_cache,
+            Some(submodules),
+            Vec::new(),
+            Vec::new(),
+            Default::default(),
+            opts.nixpkgs.clone(),
+            indent,
+        )
+    })
+}
@@ -0,0 +1,55 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+use anyhow::Result;
+use bstr::ByteSlice;
+use serde_json::{json, Value};
+
+use crate::cli::LogFormat;
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_format(format: &LogFormat) {
+    JSON.store(matches!(format, LogFormat::Json), Ordering::Relaxed);
+}
+
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+// one JSON object per line, so consumers can tell progress apart from a hang
+pub fn event(event: &str, mut fields: Value) {
+    if !json() {
+        return;
+    }
+
+    fields["event"] = event.into();
+    eprintln!("{fields}");
+}
+
+pub fn prefetch<T>(target: &str, prefetch: impl FnOnce() -> Result<T>) -> Result<T> {
+    event("prefetch_started", json!({ "target": target }));
+    let start = Instant::now();
+    let res = prefetch();
+    event(
+        "prefetch_finished",
+        json!({
+            "target": target,
+            "success": res.is_ok(),
+            "duration_ms": start.elapsed().as_millis() as u64,
+        }),
+    );
+    res
+}
+
+// stderr of spawned commands is only captured with --log-format json
+pub fn stderr(program: &str, stderr: &[u8]) {
+    for line in stderr.lines() {
+        let line = line.to_str_lossy();
+        if !line.trim().is_empty() {
+            event("stderr", json!({ "program": program, "line": line }));
+        }
+    }
+}
@@ -1,12 +1,30 @@
 use std::{
-    io::BufRead,
+    env,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs::{self, DirBuilder},
+    hash::{BuildHasher, Hasher, RandomState},
+    io::{self, BufRead, Write},
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
     process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
 };
 
-use anyhow::{anyhow, bail, Result};
-use data_encoding::BASE64;
+use anyhow::{anyhow, bail, Context, Result};
+use data_encoding::{BASE64, HEXLOWER_PERMISSIVE};
 use nix_compat::nixbase32;
 use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    audit::AuditedCommand,
+    cli::{HashAttr, HashFormat},
+    log,
+};
 
 trait GetStdout {
     fn get_stdout(&mut self) -> Result<Vec<u8>>;
@@ -14,7 +32,18 @@ trait GetStdout {
 
 impl GetStdout for Command {
     fn get_stdout(&mut self) -> Result<Vec<u8>> {
-        let Output { stdout, status, .. } = self.stderr(Stdio::inherit()).output()?;
+        let Output {
+            stdout,
+            stderr,
+            status,
+        } = self
+            .stderr(if log::json() {
+                Stdio::piped()
+            } else {
+                Stdio::inherit()
+            })
+            .audited_output()?;
+        log::stderr(&self.get_program().to_string_lossy(), &stderr);
         if !status.success() {
             bail!("command exited with {}", status);
         }
@@ -22,59 +51,555 @@ impl GetStdout for Command {
     }
 }
 
+#[macro_export]
 macro_rules! info {
     ($($tt:tt)+) => {{
         use owo_colors::{OwoColorize, Stream, Style};
-        eprintln!(
-            "{}",
-            format_args!($($tt)+).if_supports_color(Stream::Stderr, |text| text
-                .style(Style::new().blue().bold()))
-        );
+        if $crate::log::json() {
+            $crate::log::event("info", ::serde_json::json!({ "message": format!($($tt)+) }));
+        } else {
+            eprintln!(
+                "{}",
+                format_args!($($tt)+).if_supports_color(Stream::Stderr, |text| text
+                    .style(Style::new().blue().bold()))
+            );
+        }
     }};
 }
 
+#[macro_export]
+macro_rules! warn {
+    ($($tt:tt)+) => {{
+        use owo_colors::{OwoColorize, Stream, Style};
+        if $crate::gha::enabled() {
+            eprintln!("::warning::{}", $crate::gha::escape(&format!($($tt)+)));
+        }
+        if $crate::log::json() {
+            $crate::log::event(
+                "warning",
+                ::serde_json::json!({ "message": format!($($tt)+) }),
+            );
+        } else {
+            eprintln!(
+                "{}",
+                format_args!("warning: {}", format_args!($($tt)+))
+                    .if_supports_color(Stream::Stderr, |text| text
+                        .style(Style::new().yellow().bold()))
+            );
+        }
+    }};
+}
+
+#[derive(Debug)]
+pub struct FlakesUnavailable;
+
+impl Display for FlakesUnavailable {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("nix flakes are unavailable")
+    }
+}
+
+impl Error for FlakesUnavailable {}
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn dry_run() {
+    DRY_RUN.store(true, Ordering::Relaxed);
+}
+
+// returned instead of prefetching with --dry-run, contains what would have been prefetched
+#[derive(Debug)]
+pub struct DryRun(pub String);
+
+impl Display for DryRun {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("dry run")
+    }
+}
+
+impl Error for DryRun {}
+
+fn check_dry_run(prefetched: impl FnOnce() -> String) -> Result<()> {
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return Err(DryRun(prefetched()).into());
+    }
+    Ok(())
+}
+
 pub fn flake_prefetch(flake_ref: String) -> Result<String> {
+    prefetch_flake_ref(&[], &flake_ref)
+}
+
+// arguments can be read by other users through /proc, so the token goes through the environment
+pub fn flake_prefetch_authenticated(
+    flake_ref: String,
+    host: &str,
+    access_token: &str,
+) -> Result<String> {
+    let mut config = env::var("NIX_CONFIG").unwrap_or_default();
+    if !config.is_empty() && !config.ends_with('\n') {
+        config.push('\n');
+    }
+    config.push_str(&format!("extra-access-tokens = {host}={access_token}\n"));
+    prefetch_flake_ref(&[("NIX_CONFIG", &config)], &flake_ref)
+}
+
+fn prefetch_flake_ref(envs: &[(&str, &str)], flake_ref: &str) -> Result<String> {
+    use bstr::ByteSlice;
+
     #[derive(Deserialize)]
     struct PrefetchOutput {
         hash: String,
     }
 
-    info!("$ nix flake prefetch --extra-experimental-features 'nix-command flakes' --json {flake_ref}");
-    Ok(serde_json::from_slice::<PrefetchOutput>(
-        &Command::new("nix")
+    check_dry_run(|| flake_ref.into())?;
+
+    let envs_log: String = envs
+        .iter()
+        .map(|(key, value)| {
+            if value.starts_with("Authorization:") {
+                format!("{key}='Authorization: <token>' ")
+            } else if *key == "NIX_CONFIG" {
+                format!("{key}='extra-access-tokens = <token>' ")
+            } else {
+                format!("{key}={value} ")
+            }
+        })
+        .collect();
+
+    let prefetch = |features_flag| {
+        info!("$ {envs_log}nix flake prefetch {features_flag} 'nix-command flakes' --json {flake_ref}");
+        Command::new("nix")
+            .envs(envs.iter().copied())
             .arg("flake")
             .arg("prefetch")
-            .arg("--extra-experimental-features")
+            .arg(features_flag)
             .arg("nix-command flakes")
             .arg("--json")
             .arg(flake_ref)
-            .get_stdout()?,
-    )?
-    .hash)
+            .audited_output_streaming(|line| {
+                if log::json() {
+                    log::stderr("nix", line);
+                } else {
+                    let _ = io::stderr().write_all(line);
+                }
+            })
+    };
+
+    let mut output = prefetch("--extra-experimental-features")?;
+    if !output.status.success()
+        && output
+            .stderr
+            .contains_str("unrecognised flag '--extra-experimental-features'")
+    {
+        warn!("nix does not support --extra-experimental-features, retrying with --experimental-features");
+        output = prefetch("--experimental-features")?;
+    }
+
+    let Output {
+        stdout,
+        stderr,
+        status,
+    } = output;
+    if !status.success() {
+        if stderr.contains_str("experimental Nix feature")
+            || stderr.contains_str("unknown experimental feature")
+            || stderr.contains_str("is not a recognised command")
+        {
+            warn!("nix flakes are unavailable, falling back to a fixed-output derivation");
+            return Err(FlakesUnavailable.into());
+        }
+        bail!("command exited with {}", status);
+    }
+
+    Ok(serde_json::from_slice::<PrefetchOutput>(&stdout)?.hash)
 }
 
-// work around for https://github.com/NixOS/nix/issues/5291
 pub fn git_prefetch(git_scheme: bool, url: &str, rev: &str, submodules: bool) -> Result<String> {
+    git_prefetch_config(git_scheme, url, rev, submodules, None)
+}
+
+// git_prefetch relies on the credential helpers of git, this passes a token as a header instead
+pub fn git_prefetch_authenticated(
+    url: &str,
+    rev: &str,
+    submodules: bool,
+    header: &str,
+) -> Result<String> {
+    git_prefetch_config(true, url, rev, submodules, Some(header))
+}
+
+fn git_prefetch_config(
+    git_scheme: bool,
+    url: &str,
+    rev: &str,
+    submodules: bool,
+    header: Option<&str>,
+) -> Result<String> {
+    // let git fetch submodules in parallel, unless the user already configures git through the
+    // environment
+    let mut config = Vec::new();
+    let count: usize = match env::var("GIT_CONFIG_COUNT") {
+        Ok(count) => count.parse().context("failed to parse GIT_CONFIG_COUNT")?,
+        Err(_) => {
+            if submodules {
+                config.push(("submodule.fetchJobs", "0"));
+            }
+            0
+        }
+    };
+    if let Some(header) = header {
+        config.push(("http.extraHeader", header));
+    }
+
+    let mut envs = Vec::new();
+    if !config.is_empty() {
+        envs.push((
+            "GIT_CONFIG_COUNT".into(),
+            (count + config.len()).to_string(),
+        ));
+        for (i, (key, value)) in config.into_iter().enumerate() {
+            envs.push((format!("GIT_CONFIG_KEY_{}", count + i), key.into()));
+            envs.push((format!("GIT_CONFIG_VALUE_{}", count + i), value.into()));
+        }
+    }
+    let envs: &[_] = &envs
+        .iter()
+        .map(|(key, value): &(String, String)| (key.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
+
+    if !submodules {
+        return git_prefetch_params(git_scheme, url, rev, "", envs);
+    }
+
+    // fetching the history of every submodule is slow for huge repositories, and the hash
+    // does not depend on it
+    match git_prefetch_params(git_scheme, url, rev, "&submodules=1&shallow=1", envs) {
+        Err(e) if !e.is::<FlakesUnavailable>() && !e.is::<DryRun>() => {
+            warn!("shallow fetch failed, retrying with the full history");
+        }
+        hash => return hash,
+    }
+    git_prefetch_params(git_scheme, url, rev, "&submodules=1", envs)
+}
+
+// arguments of fetchgit that nix flake prefetch cannot express
+#[derive(Default)]
+pub struct GitOptions {
+    leave_dot_git: Option<bool>,
+    deep_clone: bool,
+    fetch_lfs: bool,
+}
+
+impl GitOptions {
+    // returns None unless every argument is one of the options set to a literal boolean
+    pub fn from_args(args: &[(String, String)], args_str: &[(String, String)]) -> Option<Self> {
+        if !args_str.is_empty() {
+            return None;
+        }
+
+        let mut options = Self::default();
+        for (key, value) in args {
+            let value = match value.trim() {
+                "true" => true,
+                "false" => false,
+                _ => return None,
+            };
+            match key.as_str() {
+                "leaveDotGit" => options.leave_dot_git = Some(value),
+                "deepClone" => options.deep_clone = value,
+                "fetchLFS" => options.fetch_lfs = value,
+                _ => return None,
+            }
+        }
+
+        Some(options)
+    }
+}
+
+// fetchgit runs nix-prefetch-git to build its output, so the hash matches even with .git kept,
+// returns None when nix-prefetch-git is not installed
+pub fn git_prefetch_script(
+    url: &str,
+    rev: &str,
+    submodules: bool,
+    options: &GitOptions,
+) -> Result<Option<String>> {
+    #[derive(Deserialize)]
+    struct PrefetchOutput {
+        hash: Option<String>,
+        sha256: String,
+    }
+
+    let mut flags = Vec::new();
+    if submodules {
+        flags.push("--fetch-submodules");
+    }
+    // fetchgit defaults leaveDotGit to deepClone
+    if options.leave_dot_git.unwrap_or(options.deep_clone) {
+        flags.push("--leave-dotGit");
+    }
+    if options.deep_clone {
+        flags.push("--deepClone");
+    }
+    if options.fetch_lfs {
+        flags.push("--fetch-lfs");
+    }
+
+    let flags_log: String = flags.iter().map(|flag| format!("{flag} ")).collect();
+    check_dry_run(|| format!("nix-prefetch-git {flags_log}--url {url} --rev {rev}"))?;
+    info!("$ nix-prefetch-git --quiet {flags_log}--url {url} --rev {rev}");
+    let stdout = match Command::new("nix-prefetch-git")
+        .arg("--quiet")
+        .args(&flags)
+        .arg("--url")
+        .arg(url)
+        .arg("--rev")
+        .arg(rev)
+        .get_stdout()
+    {
+        Err(e)
+            if e.downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::NotFound) =>
+        {
+            warn!("nix-prefetch-git is not installed, falling back to a fixed-output derivation");
+            return Ok(None);
+        }
+        stdout => stdout?,
+    };
+
+    // older versions of nix-prefetch-git only output the hash in base32
+    let PrefetchOutput { hash, sha256 } = serde_json::from_slice(&stdout)?;
+    Ok(Some(match hash {
+        Some(hash) => hash,
+        None => format!(
+            "sha256-{}",
+            BASE64.encode(&nixbase32::decode(sha256.as_bytes())?)
+        ),
+    }))
+}
+
+// work around for https://github.com/NixOS/nix/issues/5291
+fn git_prefetch_params(
+    git_scheme: bool,
+    url: &str,
+    rev: &str,
+    params: &str,
+    envs: &[(&str, &str)],
+) -> Result<String> {
     let prefix = if git_scheme { "" } else { "git+" };
-    let submodules = if submodules { "&submodules=1" } else { "" };
+    let prefetch = |flake_ref: String| prefetch_flake_ref(envs, &flake_ref);
 
     if rev.len() == 40 {
-        flake_prefetch(format!("{prefix}{url}?allRefs=1&rev={rev}{submodules}"))
+        prefetch(format!("{prefix}{url}?allRefs=1&rev={rev}{params}"))
     } else {
         if !rev.starts_with("refs/") {
-            if let hash @ Ok(_) =
-                flake_prefetch(format!("{prefix}{url}?ref=refs/tags/{rev}{submodules}"))
-            {
-                return hash;
+            match prefetch(format!("{prefix}{url}?ref=refs/tags/{rev}{params}")) {
+                Err(e) if !e.is::<FlakesUnavailable>() && !e.is::<DryRun>() => {}
+                hash => return hash,
             }
         }
-        flake_prefetch(format!("{prefix}{url}?ref={rev}{submodules}"))
+        prefetch(format!("{prefix}{url}?ref={rev}{params}"))
     }
 }
 
+pub fn nix_parse(expr: &str) -> Result<()> {
+    // `with` defers variable lookups, allowing references to variables defined outside the output
+    let expr = format!("with {{ }}; ({expr}\n)");
+    info!("$ nix-instantiate --parse --expr '{expr}'");
+
+    let Output { stderr, status, .. } = Command::new("nix-instantiate")
+        .arg("--parse")
+        .arg("--expr")
+        .arg(expr)
+        .audited_output()?;
+
+    if !status.success() {
+        bail!("{}", String::from_utf8_lossy(&stderr).trim_end());
+    }
+
+    Ok(())
+}
+
+pub fn git_ls_remote(url: &str) -> Result<String> {
+    info!("$ git ls-remote {url}");
+    Ok(String::from_utf8(
+        Command::new("git")
+            .arg("ls-remote")
+            .arg(url)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .get_stdout()?,
+    )?)
+}
+
+// resolves a ref, e.g. HEAD or refs/heads/main, to its commit
+pub fn git_ref(url: &str, target: &str) -> Result<String> {
+    git_ls_remote(url)?
+        .lines()
+        .find_map(|line| {
+            let (sha, name) = line.split_once('\t')?;
+            (name == target).then(|| sha.into())
+        })
+        .with_context(|| format!("failed to find {target} in {url}"))
+}
+
+// resolves a branch to its commit with a single ls-remote,
+// giving up if a tag with the same name exists since the rev would then refer to the tag
+pub fn git_branch_rev(url: &str, branch: &str) -> Option<String> {
+    let refs = git_ls_remote(url).ok()?;
+    let mut sha = None;
+    for line in refs.lines() {
+        let Some((rev, name)) = line.split_once('\t') else {
+            continue;
+        };
+        if name.strip_prefix("refs/tags/") == Some(branch) {
+            return None;
+        }
+        if name.strip_prefix("refs/heads/") == Some(branch) {
+            sha = Some(rev.into());
+        }
+    }
+    sha
+}
+
+pub fn git_tags(url: &str) -> Result<Vec<String>> {
+    Ok(git_ls_remote(url)?
+        .lines()
+        .filter_map(|line| {
+            let (_, name) = line.split_once('\t')?;
+            let tag = name.strip_prefix("refs/tags/")?;
+            (!tag.ends_with("^{}")).then(|| tag.into())
+        })
+        .collect())
+}
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    // like mkdtemp, the directory is created exclusively under an unpredictable name
+    // and is only accessible by the current user
+    fn new(prefix: &str) -> Result<Self> {
+        let tmp = env::temp_dir();
+        for _ in 0 .. 16 {
+            let suffix = RandomState::new().build_hasher().finish();
+            let path = tmp.join(format!("{prefix}-{suffix:016x}"));
+            match DirBuilder::new().mode(0o700).create(&path) {
+                Ok(()) => return Ok(Self(path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to create {}", path.display()));
+                }
+            }
+        }
+
+        bail!(
+            "failed to create a temporary directory in {}",
+            tmp.display()
+        );
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// returns the verified commit, which is what the tag or branch pointed to at the time
+pub fn git_verify_signature(url: &str, rev: &str, keyring: &Path) -> Result<String> {
+    let dir = TempDir::new("nurl-verify")?;
+    let gnupg = dir.0.join("gnupg");
+    DirBuilder::new().mode(0o700).create(&gnupg)?;
+
+    info!("$ gpg --import {}", keyring.display());
+    Command::new("gpg")
+        .arg("--batch")
+        .arg("--quiet")
+        .arg("--homedir")
+        .arg(&gnupg)
+        .arg("--import")
+        .arg(keyring)
+        .get_stdout()
+        .with_context(|| format!("failed to import {}", keyring.display()))?;
+
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(&dir.0)
+            .args(args)
+            .env("GNUPGHOME", &gnupg)
+            .get_stdout()
+    };
+    git(&["init", "--bare", "--quiet"])?;
+    let rev_parse = |commit: &str| -> Result<String> {
+        Ok(String::from_utf8(git(&["rev-parse", commit])?)?
+            .trim()
+            .into())
+    };
+
+    let tag = format!("refs/tags/{rev}");
+    info!("$ git fetch --depth 1 {url} {tag}:{tag}");
+    let commit = if git(&[
+        "fetch",
+        "--quiet",
+        "--depth",
+        "1",
+        url,
+        &format!("{tag}:{tag}"),
+    ])
+    .is_ok()
+    {
+        let commit = format!("{tag}^{{commit}}");
+        info!("$ git verify-tag {rev}");
+        if git(&["verify-tag", &tag]).is_ok() {
+            return rev_parse(&commit);
+        }
+
+        // lightweight and unsigned tags can still point to a signed commit
+        warn!("{rev} is not a signed tag, verifying the commit it points to instead");
+        commit
+    } else {
+        info!("$ git fetch --depth 1 {url} {rev}");
+        git(&["fetch", "--quiet", "--depth", "1", url, rev])
+            .with_context(|| format!("failed to fetch {rev} from {url}"))?;
+        "FETCH_HEAD".into()
+    };
+
+    info!("$ git verify-commit {commit}");
+    git(&["verify-commit", &commit])
+        .with_context(|| format!("{rev} is not signed by any key in {}", keyring.display()))?;
+
+    rev_parse(&commit)
+}
+
+pub fn bzr_revno(url: &str) -> Result<String> {
+    info!("$ bzr revno {url}");
+    Ok(
+        String::from_utf8(Command::new("bzr").arg("revno").arg(url).get_stdout()?)?
+            .trim()
+            .into(),
+    )
+}
+
+pub fn svn_last_changed_rev(url: &str) -> Result<String> {
+    info!("$ svn info --show-item last-changed-revision {url}");
+    Ok(String::from_utf8(
+        Command::new("svn")
+            .arg("info")
+            .arg("--show-item")
+            .arg("last-changed-revision")
+            .arg(url)
+            .get_stdout()?,
+    )?
+    .trim()
+    .into())
+}
+
 pub fn url_prefetch(url: String, unpack: bool) -> Result<String> {
     use bstr::ByteSlice;
 
+    check_dry_run(|| url.clone())?;
+
     let mut cmd = Command::new("nix-prefetch-url");
     if unpack {
         cmd.arg("--unpack");
@@ -91,7 +616,54 @@ pub fn url_prefetch(url: String, unpack: bool) -> Result<String> {
     ))
 }
 
+static HASH_OUTPUT: OnceLock<(HashFormat, Option<HashAttr>)> = OnceLock::new();
+
+pub fn set_hash_output(format: HashFormat, attr: Option<HashAttr>) -> Result<()> {
+    if matches!(attr, Some(HashAttr::Hash)) && !matches!(format, HashFormat::Sri) {
+        bail!("the hash attribute only accepts SRI hashes, use --hash-attr sha256 instead");
+    }
+    let _ = HASH_OUTPUT.set((format, attr));
+    Ok(())
+}
+
+// the attribute the hash is written to, older fetchers only accept non-SRI hashes as sha256
+pub fn hash_attr(default: &'static str) -> &'static str {
+    match HASH_OUTPUT.get() {
+        Some((_, Some(HashAttr::Hash))) => "hash",
+        Some((_, Some(HashAttr::Sha256)) | (HashFormat::Base32 | HashFormat::Base16, None)) => {
+            "sha256"
+        }
+        Some((HashFormat::Sri, None)) | None => default,
+    }
+}
+
+// hashes are prefetched and cached as SRI, they are only converted for the output
+pub fn format_hash(hash: &str) -> Result<String> {
+    let encode: fn(&[u8]) -> String = match HASH_OUTPUT.get() {
+        Some((HashFormat::Base32, _)) => nixbase32::encode,
+        Some((HashFormat::Base16, _)) => |digest| HEXLOWER_PERMISSIVE.encode(digest),
+        Some((HashFormat::Sri, _)) | None => return Ok(hash.into()),
+    };
+
+    match hash.split_once('-') {
+        Some(("sha256", digest)) => Ok(encode(
+            &BASE64
+                .decode(digest.as_bytes())
+                .with_context(|| format!("invalid SRI hash {hash}"))?,
+        )),
+        _ => bail!("{hash} is not a SRI sha256 hash"),
+    }
+}
+
+pub fn sri_from_hex(sha256: &str) -> Result<String> {
+    Ok(format!(
+        "sha256-{}",
+        BASE64.encode(&HEXLOWER_PERMISSIVE.decode(sha256.as_bytes())?),
+    ))
+}
+
 pub fn fod_prefetch(expr: String) -> Result<String> {
+    check_dry_run(|| format_expr(&expr))?;
     info!(
         "$ nix build --extra-experimental-features nix-command --impure --no-link --expr '{expr}'"
     );
@@ -108,7 +680,16 @@ pub fn fod_prefetch(expr: String) -> Result<String> {
         .arg("--no-link")
         .arg("--expr")
         .arg(expr)
-        .output()?;
+        .audited_output()?;
+
+    // the hash mismatch is expected, only forward what nix warns about
+    if log::json() {
+        for line in stderr.lines().map_while(io::Result::ok) {
+            if line.starts_with("warning:") {
+                log::event("stderr", json!({ "program": "nix", "line": line }));
+            }
+        }
+    }
 
     if status.success() {
         bail!(
@@ -139,3 +720,97 @@ pub fn fod_prefetch(expr: String) -> Result<String> {
         String::from_utf8_lossy(&stderr),
     ))
 }
+
+pub fn verify(expr: String) -> Result<()> {
+    info!(
+        "$ nix build --extra-experimental-features nix-command --impure --no-link --expr '{expr}'"
+    );
+
+    Command::new("nix")
+        .arg("build")
+        .arg("--extra-experimental-features")
+        .arg("nix-command")
+        .arg("--impure")
+        .arg("--no-link")
+        .arg("--expr")
+        .arg(expr)
+        .get_stdout()
+        .context("failed to verify the generated expression")?;
+
+    Ok(())
+}
+
+// breaks the generated expressions into one binding per line, only whitespace outside of
+// strings is changed
+fn format_expr(expr: &str) -> String {
+    let mut out = String::with_capacity(expr.len() * 2);
+    let mut depth = 0;
+    let mut chars = expr.chars().peekable();
+
+    let newline = |out: &mut String, depth: usize| {
+        out.truncate(out.trim_end_matches(' ').len());
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(&"  ".repeat(depth));
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '\'' if chars.peek() == Some(&'\'') => {
+                out.push_str("''");
+                chars.next();
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    if c != '\'' || chars.peek() != Some(&'\'') {
+                        continue;
+                    }
+                    out.push('\'');
+                    chars.next();
+                    match chars.peek() {
+                        Some('\'' | '$' | '\\') => out.extend(chars.next()),
+                        _ => break,
+                    }
+                }
+            }
+            '{' if chars.peek() == Some(&'}') => {
+                out.push_str("{ }");
+                chars.next();
+            }
+            '{' => {
+                if !out.ends_with(' ') {
+                    out.push(' ');
+                }
+                out.push('{');
+                depth += 1;
+                newline(&mut out, depth);
+            }
+            '}' => {
+                depth = depth.saturating_sub(1);
+                newline(&mut out, depth);
+                out.push('}');
+            }
+            ';' => {
+                out.push(';');
+                newline(&mut out, depth);
+            }
+            '=' if !out.ends_with(['=', '<', '>', '!']) && chars.peek() != Some(&'=') => {
+                out.push_str(" = ");
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
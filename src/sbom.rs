@@ -0,0 +1,210 @@
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use data_encoding::{BASE64, HEXLOWER};
+use serde_json::{json, Value};
+
+use crate::{
+    cli::SbomFormat,
+    fetcher::{json_forge_url, repo_name},
+    simple::percent_encode,
+};
+
+struct Component {
+    name: String,
+    version: String,
+    purl: String,
+    download_location: String,
+    sha256: String,
+    hash: String,
+}
+
+pub fn write_sbom(
+    out: &mut impl Write,
+    format: &SbomFormat,
+    url: &str,
+    json: &Value,
+) -> Result<()> {
+    let component = component(url, json)?;
+    let timestamp = timestamp()?;
+
+    let sbom = match format {
+        SbomFormat::Spdx => json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": component.name,
+            "documentNamespace": format!(
+                "https://spdx.org/spdxdocs/{}-{}",
+                component.name, component.sha256,
+            ),
+            "creationInfo": {
+                "created": timestamp,
+                "creators": [concat!("Tool: nurl-", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": [{
+                "SPDXID": "SPDXRef-Package",
+                "name": component.name,
+                "versionInfo": component.version,
+                "downloadLocation": component.download_location,
+                "filesAnalyzed": false,
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": component.purl,
+                }],
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": "NOASSERTION",
+                "copyrightText": "NOASSERTION",
+                // the Nix hash covers the NAR serialization rather than the downloaded file,
+                // so it can not be listed as an SPDX checksum
+                "annotations": [{
+                    "annotationType": "OTHER",
+                    "annotator": concat!("Tool: nurl-", env!("CARGO_PKG_VERSION")),
+                    "annotationDate": timestamp,
+                    "comment": format!("nix:hash {}", component.hash),
+                }],
+            }],
+            "relationships": [{
+                "spdxElementId": "SPDXRef-DOCUMENT",
+                "relationshipType": "DESCRIBES",
+                "relatedSpdxElement": "SPDXRef-Package",
+            }],
+        }),
+
+        SbomFormat::Cyclonedx => json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "metadata": {
+                "timestamp": timestamp,
+                "tools": [{
+                    "name": "nurl",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "components": [{
+                "type": "library",
+                "bom-ref": component.purl,
+                "name": component.name,
+                "version": component.version,
+                "purl": component.purl,
+                "externalReferences": [{
+                    "type": "distribution",
+                    "url": component.download_location,
+                }],
+                "properties": [{
+                    "name": "nix:hash",
+                    "value": component.hash,
+                }],
+            }],
+        }),
+    };
+
+    serde_json::to_writer(out, &sbom)?;
+
+    Ok(())
+}
+
+fn component(url: &str, json: &Value) -> Result<Component> {
+    let args = &json["args"];
+    let arg = |key| args[key].as_str();
+    let fetcher = json["fetcher"]
+        .as_str()
+        .context("failed to find the fetcher")?;
+
+    let hash = arg("hash")
+        .or_else(|| arg("sha256"))
+        .context("failed to find the hash")?;
+    let sha256 = hash
+        .strip_prefix("sha256-")
+        .and_then(|hash| BASE64.decode(hash.as_bytes()).ok())
+        .map(|hash| HEXLOWER.encode(&hash))
+        .with_context(|| format!("unsupported hash {hash}"))?;
+
+    let (name, version, purl) = match fetcher {
+        "fetchCrate" | "fetchPypi" | "fetchHex" => {
+            let name = arg("pname")
+                .or_else(|| arg("pkg"))
+                .context("failed to find the name")?;
+            let version = arg("version").context("failed to find the version")?;
+            let purl = match fetcher {
+                "fetchCrate" => format!("pkg:cargo/{name}@{}", percent_encode(version)),
+                "fetchPypi" => format!(
+                    "pkg:pypi/{}@{}",
+                    name.to_lowercase().replace('_', "-"),
+                    percent_encode(version),
+                ),
+                _ => format!("pkg:hex/{name}@{}", percent_encode(version)),
+            };
+            (name.to_owned(), version.to_owned(), purl)
+        }
+
+        _ => {
+            let rev = arg("rev").context("failed to find the revision")?;
+            // the repository rather than the URL nurl was given, e.g. an archive
+            let repo_url = json_forge_url(json)?.unwrap_or_else(|| url.into());
+            let purl = match (fetcher, arg("owner"), arg("repo")) {
+                ("fetchFromGitHub", Some(owner), Some(repo)) if arg("githubBase").is_none() => {
+                    format!("pkg:github/{owner}/{repo}@{}", percent_encode(rev))
+                }
+                ("fetchFromBitbucket", Some(owner), Some(repo)) => {
+                    format!("pkg:bitbucket/{owner}/{repo}@{}", percent_encode(rev))
+                }
+                ("fetchFromGitLab", Some(owner), Some(repo)) if arg("domain").is_none() => {
+                    let group = arg("group")
+                        .map(|group| format!("{group}/"))
+                        .unwrap_or_default();
+                    format!("pkg:gitlab/{group}{owner}/{repo}@{}", percent_encode(rev))
+                }
+                _ => format!(
+                    "pkg:generic/{}@{}?vcs_url={}",
+                    repo_name(&repo_url),
+                    percent_encode(rev),
+                    percent_encode(&format!("{repo_url}@{rev}")),
+                ),
+            };
+            (
+                arg("repo").unwrap_or(repo_name(&repo_url)).to_owned(),
+                rev.to_owned(),
+                purl,
+            )
+        }
+    };
+
+    Ok(Component {
+        name,
+        version,
+        purl,
+        download_location: url.into(),
+        sha256,
+        hash: hash.into(),
+    })
+}
+
+// formats the current time as RFC 3339 without pulling in a date library
+pub fn timestamp() -> Result<String> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    Ok(format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+    ))
+}
@@ -1,20 +1,58 @@
 use std::{fmt::Write as _, io::Write};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
+use indexmap::IndexMap;
 use itertools::Itertools;
-use rustc_hash::FxHashMap;
+use nu_glob::Pattern;
+use semver::{Version, VersionReq};
 use serde_json::{json, Value};
 
 use crate::{
-    prefetch::{flake_prefetch, fod_prefetch, git_prefetch, url_prefetch},
-    Url,
+    cache::{self, Entry},
+    info, log,
+    prefetch::{
+        flake_prefetch, flake_prefetch_authenticated, fod_prefetch, format_hash, git_prefetch,
+        git_prefetch_script, hash_attr, url_prefetch, FlakesUnavailable, GitOptions,
+    },
+    warn, Url,
 };
 
+#[derive(Default)]
+pub struct Meta {
+    pub licenses: Vec<String>,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub changelog: Option<String>,
+}
+
+impl Meta {
+    pub fn strings(&self) -> Vec<(&'static str, String)> {
+        let mut strings = Vec::new();
+
+        // nixpkgs descriptions do not end with a period
+        if let Some(description) = &self.description {
+            let description = description.trim().trim_end_matches('.');
+            if !description.is_empty() {
+                strings.push(("description", description.into()));
+            }
+        }
+        if let Some(homepage) = &self.homepage {
+            strings.push(("homepage", homepage.clone()));
+        }
+        if let Some(changelog) = &self.changelog {
+            strings.push(("changelog", changelog.clone()));
+        }
+
+        strings
+    }
+}
+
 pub trait SimpleFetcher<'a, const N: usize> {
     const HASH_KEY: &'static str = "hash";
     const HOST_KEY: &'static str = "domain";
     const KEYS: [&'static str; N];
     const NAME: &'static str;
+    const PATH_FORMAT: Option<&'static str> = None;
     const REV_KEY: &'static str = "rev";
     const SUBMODULES_DEFAULT: bool = false;
     const SUBMODULES_KEY: Option<&'static str> = None;
@@ -27,6 +65,14 @@ pub trait SimpleFetcher<'a, const N: usize> {
         None
     }
 
+    fn rev_comment(&self) -> Option<&str> {
+        None
+    }
+
+    fn private(&self) -> bool {
+        false
+    }
+
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; N]> {
         let mut xs: [_; N] = url
             .path_segments()
@@ -40,8 +86,75 @@ pub trait SimpleFetcher<'a, const N: usize> {
         Some(xs)
     }
 
-    fn resolve_submodules(&self, submodules: Option<bool>) -> bool {
-        submodules.map_or(false, |submodules| submodules ^ Self::SUBMODULES_DEFAULT)
+    // prefetched values are stored as a Vec since the number of values differs between fetchers
+    fn prefetched_values<'b>(&self, values: &'b [String]) -> Result<[&'b str; N]> {
+        values
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| anyhow!("expected {N} values for {}", Self::NAME))
+    }
+
+    fn parse_error(&self, url: &Url) -> String {
+        let expected = match Self::PATH_FORMAT {
+            Some(format) => format.into(),
+            None => format!("/{}", Self::KEYS.join("/")),
+        };
+        format!(
+            "failed to parse {url}\n{} expects a URL path of {expected}, got /{}\n\
+            use --fetcher to pick a different fetcher, e.g. --fetcher fetchgit",
+            Self::NAME,
+            url.path(),
+        )
+    }
+
+    // whether a request was redirected, which is how forges serve a moved repository
+    fn redirected(&self) -> bool {
+        false
+    }
+
+    fn resolve_redirect(&self, _: &[&str; N]) -> Option<[String; N]> {
+        None
+    }
+
+    fn resolve_submodules(
+        &self,
+        submodules: Option<bool>,
+        overwrite: Option<&str>,
+    ) -> Result<bool> {
+        let Some(key) = Self::SUBMODULES_KEY else {
+            if submodules == Some(true) {
+                warn!(
+                    "{} does not support fetching submodules, ignoring --submodules",
+                    Self::NAME
+                );
+            }
+            return Ok(false);
+        };
+
+        let overwrite = match overwrite {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            Some(expr) => {
+                warn!(
+                    "cannot evaluate the {key} overwrite `{expr}`, \
+                    the hash is computed with {key} = {}",
+                    submodules.unwrap_or(Self::SUBMODULES_DEFAULT),
+                );
+                None
+            }
+            None => None,
+        };
+
+        match (submodules, overwrite) {
+            (Some(submodules), Some(overwrite)) if submodules != overwrite => {
+                bail!("--submodules={submodules} conflicts with the {key} overwrite");
+            }
+            (submodules, overwrite) => {
+                Ok(overwrite.or(submodules).unwrap_or(Self::SUBMODULES_DEFAULT))
+            }
+        }
     }
 
     fn fetch_rev(&self, _: &[&str; N]) -> Result<String> {
@@ -51,6 +164,187 @@ pub trait SimpleFetcher<'a, const N: usize> {
         );
     }
 
+    fn fetch_branch_rev(&self, _: &[&str; N], _: &str) -> Option<String> {
+        None
+    }
+
+    fn fetch_tags(&self, _: &[&str; N]) -> Result<Vec<String>> {
+        bail!("{} does not support fetching tags", Self::NAME);
+    }
+
+    fn resolve_rev(&self, values: &[&str; N], rev: Option<String>, pin: bool) -> Result<String> {
+        let Some(rev) = rev else {
+            return self.fetch_rev(values);
+        };
+
+        if rev.len() == 40 && rev.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(rev);
+        }
+
+        let branch = rev.strip_prefix("refs/heads/").unwrap_or(&rev);
+        Ok(match self.fetch_branch_rev(values, branch) {
+            Some(sha) if pin => {
+                info!("pinned branch {branch} to {sha}");
+                sha
+            }
+            Some(sha) => {
+                warn!(
+                    "{branch} is a branch, the generated expression will break when it moves, \
+                    pass --pin to use its current commit {sha} instead",
+                );
+                rev
+            }
+            None => rev,
+        })
+    }
+
+    fn compare_url(&self, _: &[&str; N], _: &str, _: &str) -> Option<String> {
+        None
+    }
+
+    fn count_commits(&self, _: &[&str; N], _: &str, _: &str) -> Option<u64> {
+        None
+    }
+
+    fn fetch_meta(&self, _: &[&str; N]) -> Option<Meta> {
+        None
+    }
+
+    fn changelog_url(&self, _: &[&str; N], _: &str) -> Option<String> {
+        None
+    }
+
+    fn immutable_rev(&self, rev: &str) -> bool {
+        rev.len() == 40 && rev.bytes().all(|c| c.is_ascii_hexdigit())
+    }
+
+    fn cache_key(
+        &self,
+        values: &[&str; N],
+        rev: Option<&str>,
+        submodules: bool,
+        args: &[(String, String)],
+        args_str: &[(String, String)],
+    ) -> Option<String> {
+        let rev = rev.filter(|rev| self.immutable_rev(rev))?;
+        Some(
+            json!([
+                Self::NAME,
+                self.host(),
+                self.group(),
+                self.private(),
+                values,
+                rev,
+                submodules,
+                args,
+                args_str,
+            ])
+            .to_string(),
+        )
+    }
+
+    fn resolve_and_fetch(
+        &self,
+        url: &Url,
+        values: &[&str; N],
+        rev: Option<String>,
+        pin: bool,
+        cache: bool,
+        hash_only: bool,
+        submodules: bool,
+        args: &[(String, String)],
+        args_str: Vec<(String, String)>,
+        fetch: impl FnOnce(&[&str; N], &str, &[(String, String)]) -> Result<String>,
+    ) -> Result<([String; N], String, Vec<(String, String)>, String)> {
+        log::event(
+            "fetcher",
+            json!({ "fetcher": Self::NAME, "url": url.as_str() }),
+        );
+
+        let key = if cache {
+            self.cache_key(values, rev.as_deref(), submodules, args, &args_str)
+        } else {
+            None
+        };
+
+        if let Some(entry) = key.as_deref().and_then(cache::get) {
+            if let Ok(values) = entry.values.try_into() {
+                info!("using the cached hash of {url} at {}", entry.rev);
+                log::event(
+                    "hash",
+                    json!({ "rev": &entry.rev, "hash": &entry.hash, "cached": true }),
+                );
+                return Ok((values, entry.rev, entry.args_str, entry.hash));
+            }
+        }
+
+        let (canonical, rev) = if hash_only {
+            // the hash depends on neither the canonical location nor whether rev is a branch
+            let rev = match rev {
+                Some(rev) if !pin => Ok(rev),
+                rev => self.resolve_rev(values, rev, pin),
+            };
+            (None, rev)
+        } else {
+            // forges keep serving the old path after a move, so the canonical one is only looked
+            // up when the API redirected or the revision could not be resolved
+            let resolved = self.resolve_rev(values, rev.clone(), pin);
+            let canonical = if resolved.is_err() || self.redirected() {
+                self.resolve_redirect(values)
+            } else {
+                None
+            };
+            let rev = match &canonical {
+                Some(canonical) if resolved.is_err() => {
+                    self.resolve_rev(&canonical.each_ref().map(String::as_str), rev, pin)
+                }
+                _ => resolved,
+            };
+            (canonical, rev)
+        };
+        let canonical = canonical
+            .as_ref()
+            .map(|xs| xs.each_ref().map(String::as_str));
+        let values = canonical.as_ref().unwrap_or(values);
+
+        let rev = rev?;
+        log::event("rev", json!({ "rev": &rev }));
+        let mut args_str = args_str;
+        self.infer_args(values, &rev, args, &mut args_str)?;
+
+        let hash = log::prefetch(url.as_str(), || fetch(values, &rev, &args_str))?;
+        log::event(
+            "hash",
+            json!({ "rev": &rev, "hash": &hash, "cached": false }),
+        );
+        let values = values.map(String::from);
+
+        // entries are also used to generate expressions, which need the canonical location
+        if let Some(key) = key.filter(|_| !hash_only) {
+            cache::insert(
+                key,
+                Entry {
+                    values: values.to_vec(),
+                    rev: rev.clone(),
+                    args_str: args_str.clone(),
+                    hash: hash.clone(),
+                },
+            );
+        }
+
+        Ok((values, rev, args_str, hash))
+    }
+
+    fn infer_args(
+        &self,
+        _: &[&str; N],
+        _: &str,
+        _: &[(String, String)],
+        _: &mut Vec<(String, String)>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     fn fetch_fod(
         &self,
         values: &[&str; N],
@@ -62,31 +356,37 @@ pub trait SimpleFetcher<'a, const N: usize> {
     ) -> Result<String> {
         let mut expr = format!(r#"(import({nixpkgs}){{}}).{}{{"#, Self::NAME);
 
+        // the values come from the URL, which can contain quotes and interpolations
         if let Some(host) = self.host() {
-            write!(expr, r#"{}="{host}";"#, Self::HOST_KEY)?;
+            write!(expr, r#"{}="{}";"#, Self::HOST_KEY, nix_escape(host))?;
         }
 
         if let Some(group) = self.group() {
-            write!(expr, r#"group="{group}";"#)?;
+            write!(expr, r#"group="{}";"#, nix_escape(group))?;
         }
 
         for (key, value) in Self::KEYS.iter().zip(values) {
-            write!(expr, r#"{key}="{value}";"#)?;
+            write!(expr, r#"{key}="{}";"#, nix_escape(value))?;
         }
 
         write!(
             expr,
-            r#"{}="{rev}";{}="sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";"#,
+            r#"{}="{}";{}="sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";"#,
             Self::REV_KEY,
+            nix_escape(rev),
             Self::HASH_KEY,
         )?;
 
-        if submodules {
+        if submodules != Self::SUBMODULES_DEFAULT {
             if let Some(key) = Self::SUBMODULES_KEY {
-                write!(expr, "{key}={};", !Self::SUBMODULES_DEFAULT)?;
+                write!(expr, "{key}={submodules};")?;
             }
         }
 
+        if self.private() {
+            expr.push_str("private=true;");
+        }
+
         for (key, value) in args {
             write!(expr, "{key}={value};")?;
         }
@@ -108,58 +408,77 @@ pub trait SimpleFetcher<'a, const N: usize> {
         submodules: bool,
         args: Vec<(String, String)>,
         args_str: Vec<(String, String)>,
-        overwrites: FxHashMap<String, String>,
+        overwrites: IndexMap<String, String>,
         indent: String,
     ) -> Result<()> {
         let mut overwrites = overwrites;
+        let hash_key = hash_attr(Self::HASH_KEY);
+        let hash = format_hash(&hash)?;
 
         writeln!(out, "{} {{", Self::NAME)?;
 
-        if let Some(host) = overwrites.remove(Self::HOST_KEY) {
+        if let Some(host) = overwrites.shift_remove(Self::HOST_KEY) {
             writeln!(out, r#"{indent}  {} = {host};"#, Self::HOST_KEY)?;
         } else if let Some(host) = self.host() {
             writeln!(out, r#"{indent}  {} = "{host}";"#, Self::HOST_KEY)?;
         }
 
-        if let Some(group) = overwrites.remove("group") {
+        if let Some(group) = overwrites.shift_remove("group") {
             writeln!(out, r#"{indent}  group = {group};"#)?;
         } else if let Some(group) = self.group() {
             writeln!(out, r#"{indent}  group = "{group}";"#)?;
         }
 
         for (key, value) in Self::KEYS.iter().zip(values) {
-            if let Some(value) = overwrites.remove(*key) {
+            if let Some(value) = overwrites.shift_remove(*key) {
                 writeln!(out, r#"{indent}  {key} = {value};"#)?;
             } else {
                 writeln!(out, r#"{indent}  {key} = "{value}";"#)?;
             }
         }
 
-        if let Some(rev) = overwrites.remove(Self::REV_KEY) {
+        if let Some(comment) = self.rev_comment() {
+            writeln!(out, "{indent}  # {comment}")?;
+        }
+        if let Some(rev) = overwrites.shift_remove(Self::REV_KEY) {
             writeln!(out, "{indent}  {} = {rev};", Self::REV_KEY)?;
         } else {
-            writeln!(out, r#"{indent}  {} = "{rev}";"#, Self::REV_KEY)?;
+            writeln!(
+                out,
+                r#"{indent}  {} = "{}";"#,
+                Self::REV_KEY,
+                nix_escape(&rev)
+            )?;
         }
-        if let Some(hash) = overwrites.remove(Self::HASH_KEY) {
-            writeln!(out, "{indent}  {} = {hash};", Self::HASH_KEY)?;
+        if let Some(hash) = overwrites.shift_remove(hash_key) {
+            writeln!(out, "{indent}  {hash_key} = {hash};")?;
         } else {
-            writeln!(out, r#"{indent}  {} = "{hash}";"#, Self::HASH_KEY)?;
+            writeln!(out, r#"{indent}  {hash_key} = "{hash}";"#)?;
         }
 
         if let Some(key) = Self::SUBMODULES_KEY {
-            if let Some(submodules) = overwrites.remove(key) {
+            if let Some(submodules) = overwrites.shift_remove(key) {
+                writeln!(out, "{indent}  {key} = {submodules};")?;
+            } else if submodules != Self::SUBMODULES_DEFAULT {
                 writeln!(out, "{indent}  {key} = {submodules};")?;
-            } else if submodules {
-                writeln!(out, "{indent}  {key} = {};", !Self::SUBMODULES_DEFAULT)?;
             }
         }
 
+        if self.private() {
+            let private = overwrites.shift_remove("private");
+            writeln!(
+                out,
+                "{indent}  private = {};",
+                private.as_deref().unwrap_or("true"),
+            )?;
+        }
+
         for (key, value) in args {
-            let value = overwrites.remove(&key).unwrap_or(value);
+            let value = overwrites.shift_remove(&key).unwrap_or(value);
             writeln!(out, "{indent}  {key} = {value};")?;
         }
         for (key, value) in args_str {
-            if let Some(value) = overwrites.remove(&key) {
+            if let Some(value) = overwrites.shift_remove(&key) {
                 writeln!(out, "{indent}  {key} = {value};")?;
             } else {
                 writeln!(out, r#"{indent}  {key} = "{value}";"#)?;
@@ -175,9 +494,8 @@ pub trait SimpleFetcher<'a, const N: usize> {
         Ok(())
     }
 
-    fn write_json(
+    fn json(
         &self,
-        out: &mut impl Write,
         values: &[&str; N],
         rev: String,
         hash: String,
@@ -186,10 +504,11 @@ pub trait SimpleFetcher<'a, const N: usize> {
         args_str: Vec<(String, String)>,
         overwrites: Vec<(String, String)>,
         overwrites_str: Vec<(String, String)>,
-    ) -> Result<()> {
+    ) -> Result<Value> {
+        let hash = format_hash(&hash)?;
         let mut fetcher_args = Value::from_iter(Self::KEYS.into_iter().zip(*values).chain([
             (Self::REV_KEY, rev.as_ref()),
-            (Self::HASH_KEY, hash.as_ref()),
+            (hash_attr(Self::HASH_KEY), hash.as_ref()),
         ]));
 
         if let Some(host) = self.host() {
@@ -200,12 +519,16 @@ pub trait SimpleFetcher<'a, const N: usize> {
             fetcher_args["group"] = json!(group);
         }
 
-        if submodules {
+        if submodules != Self::SUBMODULES_DEFAULT {
             if let Some(key) = Self::SUBMODULES_KEY {
-                fetcher_args[key] = json!(!Self::SUBMODULES_DEFAULT);
+                fetcher_args[key] = json!(submodules);
             }
         }
 
+        if self.private() {
+            fetcher_args["private"] = json!(true);
+        }
+
         for (key, value) in args {
             fetcher_args[key] = json!({
                 "type": "nix",
@@ -226,15 +549,10 @@ pub trait SimpleFetcher<'a, const N: usize> {
             fetcher_args[key] = json!(value);
         }
 
-        serde_json::to_writer(
-            out,
-            &json!({
-                "fetcher": Self::NAME,
-                "args": fetcher_args,
-            }),
-        )?;
-
-        Ok(())
+        Ok(json!({
+            "fetcher": Self::NAME,
+            "args": fetcher_args,
+        }))
     }
 }
 
@@ -265,10 +583,12 @@ pub trait SimpleFlakeFetcher<'a, const N: usize>: SimpleFetcher<'a, N> {
         nixpkgs: String,
     ) -> Result<String> {
         if args.is_empty() && args_str.is_empty() {
-            flake_prefetch(self.get_flake_ref(values, rev, submodules))
-        } else {
-            self.fetch_fod(values, rev, submodules, args, args_str, nixpkgs)
+            match flake_prefetch(self.get_flake_ref(values, rev, submodules)) {
+                Err(e) if e.is::<FlakesUnavailable>() => {}
+                hash => return hash,
+            }
         }
+        self.fetch_fod(values, rev, submodules, args, args_str, nixpkgs)
     }
 }
 
@@ -277,6 +597,14 @@ pub trait SimpleGitFetcher<'a, const N: usize>: SimpleFetcher<'a, N> {
 
     fn get_repo_url(&self, values: &[&str; N]) -> String;
 
+    fn get_archive_url(&self, _: &[&str; N], _: &str) -> Option<String> {
+        None
+    }
+
+    fn access_token(&self) -> Option<(&str, String)> {
+        None
+    }
+
     fn fetch(
         &self,
         values: &[&str; N],
@@ -287,19 +615,27 @@ pub trait SimpleGitFetcher<'a, const N: usize>: SimpleFetcher<'a, N> {
         nixpkgs: String,
     ) -> Result<String> {
         if args.is_empty() && args_str.is_empty() {
-            if submodules {
-                git_prefetch(
-                    true,
-                    &self.get_repo_url(values),
-                    rev,
-                    !Self::SUBMODULES_DEFAULT,
-                )
+            let hash = if submodules {
+                git_prefetch(true, &self.get_repo_url(values), rev, true)
+            } else if let Some((host, access_token)) = self.access_token() {
+                flake_prefetch_authenticated(self.get_flake_ref(values, rev), host, &access_token)
+            } else if let Some(url) = self.get_archive_url(values, rev) {
+                url_prefetch(url, true)
             } else {
                 flake_prefetch(self.get_flake_ref(values, rev))
+            };
+            match hash {
+                Err(e) if e.is::<FlakesUnavailable>() => {}
+                hash => return hash,
+            }
+        } else if let Some(options) = GitOptions::from_args(args, args_str) {
+            let url = self.get_repo_url(values);
+            let url = url.strip_prefix("git+").unwrap_or(&url);
+            if let Some(hash) = git_prefetch_script(url, rev, submodules, &options)? {
+                return Ok(hash);
             }
-        } else {
-            self.fetch_fod(values, rev, submodules, args, args_str, nixpkgs)
         }
+        self.fetch_fod(values, rev, submodules, args, args_str, nixpkgs)
     }
 }
 
@@ -308,6 +644,10 @@ pub trait SimpleUrlFetcher<'a, const N: usize>: SimpleFetcher<'a, N> {
 
     fn get_url(&self, values: &[&str; N], rev: &str) -> String;
 
+    fn fetch_upstream_hash(&self, _: &[&str; N], _: &str) -> Option<String> {
+        None
+    }
+
     fn fetch(
         &self,
         values: &[&str; N],
@@ -318,9 +658,153 @@ pub trait SimpleUrlFetcher<'a, const N: usize>: SimpleFetcher<'a, N> {
         nixpkgs: String,
     ) -> Result<String> {
         if args.is_empty() && args_str.is_empty() {
+            if let Some(hash) = self.fetch_upstream_hash(values, rev) {
+                return Ok(hash);
+            }
             url_prefetch(self.get_url(values, rev), Self::UNPACK)
         } else {
             self.fetch_fod(values, rev, submodules, args, args_str, nixpkgs)
         }
     }
 }
+
+// the tags considered with --tag, from --tag-filter and --tag-semver
+#[derive(Default)]
+pub struct TagFilter {
+    pub glob: Option<Pattern>,
+    pub semver: Option<VersionReq>,
+}
+
+impl TagFilter {
+    fn matches(&self, tag: &str) -> bool {
+        let version = version_key(tag);
+        if version.is_empty() || self.glob.as_ref().is_some_and(|glob| !glob.matches(tag)) {
+            return false;
+        }
+
+        // missing components are zero, e.g. v1.2 is 1.2.0
+        self.semver.as_ref().map_or(true, |req| {
+            let component = |i| version.get(i).copied().unwrap_or(0);
+            req.matches(&Version::new(component(0), component(1), component(2)))
+        })
+    }
+}
+
+// picks the highest version instead of the most recent tag, since tags are not always created in
+// order, and skips pre-releases
+pub fn latest_tag(tags: Vec<String>, filter: &TagFilter) -> Option<String> {
+    tags.into_iter()
+        .filter(|tag| !is_prerelease(tag) && filter.matches(tag))
+        // the first of equal versions, tags are listed from the newest on forges
+        .rev()
+        .max_by_key(|tag| version_key(tag))
+}
+
+fn is_prerelease(tag: &str) -> bool {
+    tag.split(|c: char| !c.is_ascii_alphabetic()).any(|word| {
+        matches!(
+            word.to_ascii_lowercase().as_str(),
+            "a" | "alpha"
+                | "b"
+                | "beta"
+                | "dev"
+                | "nightly"
+                | "pre"
+                | "preview"
+                | "rc"
+                | "snapshot",
+        )
+    })
+}
+
+fn version_key(tag: &str) -> Vec<u64> {
+    tag.split(|c: char| !c.is_ascii_digit())
+        .filter_map(|x| x.parse().ok())
+        .collect()
+}
+
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(b as char);
+        } else {
+            let _ = write!(encoded, "%{b:02X}");
+        }
+    }
+    encoded
+}
+
+pub fn nix_escape(s: &str) -> String {
+    s.replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace("${", r"\${")
+}
+
+// renders JSON as a Nix value, nested lists and attribute sets are indented relative to indent
+pub fn nix_value(value: &Value, indent: &str) -> String {
+    match value {
+        Value::Null => "null".into(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!(r#""{}""#, nix_escape(s)),
+        Value::Array(xs) if xs.is_empty() => "[ ]".into(),
+        Value::Array(xs) if xs.iter().all(|x| !x.is_array() && !x.is_object()) => {
+            format!(
+                "[ {} ]",
+                xs.iter().map(|x| nix_element(x, indent)).join(" ")
+            )
+        }
+        Value::Array(xs) => {
+            let mut nix = String::from("[\n");
+            for x in xs {
+                let _ = writeln!(nix, "{indent}  {}", nix_element(x, &format!("{indent}  ")));
+            }
+            nix.push_str(indent);
+            nix.push(']');
+            nix
+        }
+        Value::Object(attrs) if attrs.is_empty() => "{ }".into(),
+        Value::Object(attrs) => {
+            let mut nix = String::from("{\n");
+            for (key, value) in attrs {
+                let _ = writeln!(
+                    nix,
+                    "{indent}  {} = {};",
+                    nix_attr_name(key),
+                    nix_value(value, &format!("{indent}  ")),
+                );
+            }
+            nix.push_str(indent);
+            nix.push('}');
+            nix
+        }
+    }
+}
+
+// negative numbers would be parsed as a subtraction from the previous element
+fn nix_element(value: &Value, indent: &str) -> String {
+    let nix = nix_value(value, indent);
+    if nix.starts_with('-') {
+        format!("({nix})")
+    } else {
+        nix
+    }
+}
+
+pub fn nix_attr_name(name: &str) -> String {
+    let mut bytes = name.bytes();
+    if bytes
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == b'_')
+        && bytes.all(|c| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'\''))
+        && !matches!(
+            name,
+            "assert" | "else" | "if" | "in" | "inherit" | "let" | "or" | "rec" | "then" | "with",
+        )
+    {
+        name.into()
+    } else {
+        format!(r#""{}""#, nix_escape(name))
+    }
+}
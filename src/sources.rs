@@ -0,0 +1,229 @@
+use std::{collections::BTreeMap, fmt::Write as _, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    cli::{FetcherFunction, Opts},
+    fetcher::Fetcher,
+    info, parse_url,
+    simple::nix_escape,
+    update::{parse_attrs, skip_trivia, Attr},
+    warn, with_fetcher,
+};
+
+#[derive(Default, Deserialize, Serialize)]
+struct LockFile {
+    version: u32,
+    sources: BTreeMap<String, Locked>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Locked {
+    original: Original,
+    fetcher: String,
+    args: Value,
+}
+
+// a source as declared in the manifest, used to detect changes
+#[derive(Deserialize, PartialEq, Serialize)]
+struct Original {
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fetcher: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    submodules: Option<bool>,
+}
+
+pub fn lock(opts: &Opts, manifest: &Path, update: bool) -> Result<()> {
+    let src = fs::read_to_string(manifest)
+        .with_context(|| format!("failed to read {}", manifest.display()))?;
+    let sources =
+        parse_manifest(&src).with_context(|| format!("failed to parse {}", manifest.display()))?;
+
+    let path = manifest.with_file_name("nurl.lock");
+    let mut old = if path.exists() {
+        let lock = fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_slice(&lock)
+            .with_context(|| format!("failed to parse {}", path.display()))?
+    } else {
+        LockFile::default()
+    };
+
+    let mut lock = LockFile {
+        version: 1,
+        sources: BTreeMap::new(),
+    };
+    for (name, original) in sources {
+        match old.sources.remove(&name) {
+            Some(locked) if !update && locked.original == original => {
+                lock.sources.insert(name, locked);
+            }
+            _ => {
+                info!("locking {name}");
+                let locked =
+                    resolve(opts, original).with_context(|| format!("failed to lock {name}"))?;
+                lock.sources.insert(name, locked);
+            }
+        }
+    }
+
+    for name in old.sources.keys() {
+        info!("removing {name}");
+    }
+
+    let mut json = serde_json::to_string_pretty(&lock)?;
+    json.push('\n');
+    fs::write(&path, json).with_context(|| format!("failed to write {}", path.display()))?;
+    info!("updated {}", path.display());
+
+    Ok(())
+}
+
+pub fn sync(opts: &Opts, path: &Path) -> Result<()> {
+    let lock = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let lock: LockFile = serde_json::from_slice(&lock)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+
+    let indent = " ".repeat(opts.indent);
+    let mut nix = format!(
+        "# generated by nurl sync from {}, do not edit\n\
+        {{ pkgs ? import <nixpkgs> {{ }} }}:\n\n{{\n",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+    );
+    for (name, locked) in &lock.sources {
+        let fetcher = if locked.fetcher.starts_with("builtins.") {
+            locked.fetcher.clone()
+        } else {
+            format!("pkgs.{}", locked.fetcher)
+        };
+        writeln!(nix, "{indent}  {name} = {fetcher} {{")?;
+
+        let args = locked
+            .args
+            .as_object()
+            .with_context(|| format!("invalid arguments for {name}"))?;
+        for (key, value) in args {
+            let value = match value {
+                Value::String(value) => format!(r#""{}""#, nix_escape(value)),
+                Value::Bool(value) => value.to_string(),
+                Value::Object(_) if value["type"] == "nix" => value["value"]
+                    .as_str()
+                    .with_context(|| format!("invalid value for {name}.{key}"))?
+                    .into(),
+                _ => bail!("invalid value for {name}.{key}"),
+            };
+            writeln!(nix, "{indent}    {key} = {value};")?;
+        }
+
+        writeln!(nix, "{indent}  }};")?;
+    }
+    nix.push_str("}\n");
+
+    let out = path.with_file_name("sources.nix");
+    fs::write(&out, nix).with_context(|| format!("failed to write {}", out.display()))?;
+    info!("updated {}", out.display());
+
+    Ok(())
+}
+
+fn resolve(opts: &Opts, original: Original) -> Result<Locked> {
+    let fetcher = original
+        .fetcher
+        .as_deref()
+        .map(|fetcher| FetcherFunction::from_str(fetcher, false).map_err(anyhow::Error::msg))
+        .transpose()?;
+
+    let url = parse_url(opts, &original.url)?;
+    let mut json = with_fetcher(opts, fetcher, &url, |fetcher, url| {
+        fetcher.fetch_json(
+            url,
+            original.rev.clone(),
+            false,
+            !opts.no_cache,
+            original.submodules,
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            opts.nixpkgs.clone(),
+        )
+    })?;
+
+    Ok(Locked {
+        original,
+        fetcher: json["fetcher"]
+            .as_str()
+            .context("failed to find the fetcher")?
+            .into(),
+        args: json["args"].take(),
+    })
+}
+
+// the manifest is an attribute set of URLs,
+// or attribute sets with url and optionally rev, fetcher, and submodules
+fn parse_manifest(src: &str) -> Result<BTreeMap<String, Original>> {
+    let open = skip_trivia(src, 0);
+    if !src[open ..].starts_with('{') {
+        bail!("expected an attribute set");
+    }
+    let (attrs, _) = parse_attrs(src, open + 1).context("invalid attribute set")?;
+
+    let mut sources = BTreeMap::new();
+    for attr in attrs {
+        let original = if let Some(url) = attr.string() {
+            Original {
+                url: url.into(),
+                rev: None,
+                fetcher: None,
+                submodules: None,
+            }
+        } else if attr.value.starts_with('{') {
+            let (attrs, _) = parse_attrs(attr.value, 1)
+                .with_context(|| format!("{} is not a valid attribute set", attr.name))?;
+            parse_source(attr.name, &attrs)?
+        } else {
+            bail!("{} is neither a string nor an attribute set", attr.name);
+        };
+        sources.insert(attr.name.into(), original);
+    }
+
+    Ok(sources)
+}
+
+fn parse_source(name: &str, attrs: &[Attr]) -> Result<Original> {
+    let string = |key: &str| {
+        attrs
+            .iter()
+            .find(|attr| attr.name == key)
+            .map(|attr| {
+                attr.string()
+                    .with_context(|| format!("{name}.{key} is not a string literal"))
+            })
+            .transpose()
+    };
+
+    for attr in attrs {
+        if !["fetcher", "rev", "submodules", "url"].contains(&attr.name) {
+            warn!("ignoring {name}.{}", attr.name);
+        }
+    }
+
+    Ok(Original {
+        url: string("url")?
+            .with_context(|| format!("{name}.url is not specified"))?
+            .into(),
+        rev: string("rev")?.map(Into::into),
+        fetcher: string("fetcher")?.map(Into::into),
+        submodules: match attrs.iter().find(|attr| attr.name == "submodules") {
+            Some(Attr { value: "true", .. }) => Some(true),
+            Some(Attr { value: "false", .. }) => Some(false),
+            Some(attr) => bail!("cannot evaluate {name}.submodules = {}", attr.value),
+            None => None,
+        },
+    })
+}
@@ -0,0 +1,1092 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use nu_glob::Pattern;
+use serde_json::{json, Value};
+
+use crate::{
+    cli::{FetcherFunction, Opts, UpdateArgs},
+    fetcher::{forge_url, Fetcher},
+    info, latest_tag,
+    simple::nix_escape,
+    template_version, warn, with_fetcher,
+};
+
+// arguments that nurl reconstructs the URL from or that do not affect the hash
+const KNOWN_ARGS: [&str; 23] = [
+    "domain",
+    "fetchSubmodules",
+    "fetchSubrepos",
+    "githubBase",
+    "group",
+    "hash",
+    "manifest",
+    "meta",
+    "name",
+    "owner",
+    "passthru",
+    "pkg",
+    "pname",
+    "pos",
+    "private",
+    "registryDl",
+    "repo",
+    "rev",
+    "sha256",
+    "tag",
+    "url",
+    "vc",
+    "version",
+];
+
+pub struct Attr<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+    span: Range<usize>,
+}
+
+impl<'a> Attr<'a> {
+    pub fn string(&self) -> Option<&'a str> {
+        let s = self.value.strip_prefix('"')?.strip_suffix('"')?;
+        (!s.contains(['"', '\\']) && !s.contains("${")).then_some(s)
+    }
+}
+
+struct Bump {
+    url: String,
+    old_rev: String,
+    new_rev: String,
+    edits: Vec<(Range<usize>, String)>,
+    changes: Vec<(String, String)>,
+    compare_url: Option<String>,
+    commits: Option<u64>,
+}
+
+impl Bump {
+    fn summary(&self) -> String {
+        if self.edits.is_empty() {
+            return "is up to date".into();
+        }
+
+        let mut summary = format!("{} -> {}", self.old_rev, self.new_rev);
+        if let Some(commits) = self.commits {
+            let plural = if commits == 1 { "" } else { "s" };
+            summary.push_str(&format!("\n  {commits} new commit{plural}"));
+        }
+        if let Some(compare_url) = &self.compare_url {
+            summary.push_str(&format!("\n  {compare_url}"));
+        }
+
+        summary
+    }
+
+    // an entry of the commit protocol of nixpkgs update scripts,
+    // update.py fills in attrPath from the package when it is missing
+    fn commit_entry(&self, path: &Path) -> Result<Value> {
+        let mut entry = json!({
+            "oldVersion": self.old_rev,
+            "newVersion": self.new_rev,
+            "files": [fs::canonicalize(path)?],
+            "changes": self
+                .changes
+                .iter()
+                .map(|(name, value)| (name.clone(), value.as_str().into()))
+                .collect::<serde_json::Map<_, _>>(),
+        });
+
+        if let Ok(attr_path) = env::var("UPDATE_NIX_ATTR_PATH") {
+            entry["attrPath"] = attr_path.into();
+        }
+        if let Some(compare_url) = &self.compare_url {
+            entry["commitBody"] = compare_url.as_str().into();
+        }
+
+        Ok(entry)
+    }
+}
+
+struct Call<'a> {
+    fetcher: FetcherFunction,
+    name: &'a str,
+    attr: Option<&'a str>,
+    start: usize,
+    line: usize,
+    attrs: Vec<Attr<'a>>,
+    // the version binding in scope of the call, e.g. of the derivation
+    version: Option<Attr<'a>>,
+}
+
+impl<'a> Call<'a> {
+    fn get(&self, name: &str) -> Option<&Attr<'a>> {
+        self.attrs.iter().find(|attr| attr.name == name)
+    }
+
+    fn string(&self, name: &str) -> Result<Option<&'a str>> {
+        self.get(name)
+            .map(|attr| {
+                attr.string()
+                    .with_context(|| format!("{name} is not a string literal"))
+            })
+            .transpose()
+    }
+
+    fn required(&self, name: &str) -> Result<&'a str> {
+        self.string(name)?
+            .with_context(|| format!("{name} is not specified"))
+    }
+}
+
+// the expressions around a fetcher call that can bind the version it refers to
+enum Scope {
+    Let(usize),
+    Rec(usize),
+    Other,
+}
+
+pub fn update(out: &mut impl Write, opts: &Opts, args: &UpdateArgs) -> Result<()> {
+    let mut changes = args.commit_json.then(Vec::new);
+    let mut count = 0;
+    for file in find_files(&args.path, args.recursive, &args.include, &args.exclude)? {
+        match update_file(out, opts, &file, &args.attr, args.dry_run, changes.as_mut()) {
+            Ok(n) => count += n,
+            Err(e) if args.recursive => warn!("skipping {}: {e:#}", file.display()),
+            Err(e) => return Err(e),
+        }
+    }
+
+    if count == 0 && !args.attr.is_empty() {
+        bail!("no fetcher calls found within {}", args.attr.join(", "));
+    }
+
+    if let Some(changes) = changes {
+        serde_json::to_writer(out, &changes)?;
+    }
+
+    Ok(())
+}
+
+// returns whether any of the fetcher calls is outdated
+pub fn outdated(
+    out: &mut impl Write,
+    opts: &Opts,
+    path: &Path,
+    recursive: bool,
+    renovate: bool,
+) -> Result<bool> {
+    let mut outdated = false;
+
+    if renovate {
+        let mut deps = Vec::new();
+        for file in find_files(path, recursive, &[], &[])? {
+            for (dep_outdated, dep) in renovate_deps(opts, &file)? {
+                outdated |= dep_outdated;
+                deps.push(dep);
+            }
+        }
+        serde_json::to_writer(out, &deps)?;
+        return Ok(outdated);
+    }
+
+    for file in find_files(path, recursive, &[], &[])? {
+        for call in outdated_calls(opts, &file)? {
+            outdated = true;
+            serde_json::to_writer(&mut *out, &call)?;
+            writeln!(out)?;
+        }
+    }
+
+    Ok(outdated)
+}
+
+// commit hashes are digests to Renovate, anything else is a version
+fn renovate_deps(opts: &Opts, path: &Path) -> Result<Vec<(bool, Value)>> {
+    let src =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+
+    let split = |rev: &str| {
+        if rev.len() == 40 && rev.bytes().all(|c| c.is_ascii_hexdigit()) {
+            (None, Some(rev.to_owned()))
+        } else {
+            (Some(rev.to_owned()), None)
+        }
+    };
+
+    let mut deps = Vec::new();
+    for call in find_calls(&src) {
+        match update_call(opts, &call, false) {
+            Ok(bump) => {
+                let (current_value, current_digest) = split(&bump.old_rev);
+                let (new_value, new_digest) = split(&bump.new_rev);
+                deps.push((
+                    !bump.edits.is_empty(),
+                    json!({
+                        "depName": bump.url,
+                        "file": path,
+                        "line": call.line,
+                        "fetcher": call.name,
+                        "currentValue": current_value,
+                        "currentDigest": current_digest,
+                        "newValue": new_value,
+                        "newDigest": new_digest,
+                    }),
+                ));
+            }
+            Err(e) => {
+                warn!(
+                    "skipping {} at {}:{}: {e:#}",
+                    call.name,
+                    path.display(),
+                    call.line,
+                );
+            }
+        }
+    }
+
+    Ok(deps)
+}
+
+pub fn outdated_calls(opts: &Opts, path: &Path) -> Result<Vec<Value>> {
+    let src =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+
+    let mut calls = Vec::new();
+    for call in find_calls(&src) {
+        match update_call(opts, &call, false) {
+            Ok(bump) if bump.edits.is_empty() => {}
+            Ok(bump) => calls.push(json!({
+                "file": path,
+                "line": call.line,
+                "fetcher": call.name,
+                "old": bump.old_rev,
+                "new": bump.new_rev,
+            })),
+            Err(e) => {
+                warn!(
+                    "skipping {} at {}:{}: {e:#}",
+                    call.name,
+                    path.display(),
+                    call.line,
+                );
+            }
+        }
+    }
+
+    Ok(calls)
+}
+
+// the same as outdated_calls, except that a call is only prefetched when its latest revision
+// changed since the last check, which is remembered in latest by line
+pub fn watch_calls(
+    opts: &Opts,
+    path: &Path,
+    latest: &mut HashMap<usize, String>,
+) -> Result<Vec<Value>> {
+    let src =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+
+    let mut calls = Vec::new();
+    for call in find_calls(&src) {
+        let new_rev = match latest_call_rev(opts, &call) {
+            Ok(Some(new_rev)) => new_rev,
+            Ok(None) => {
+                latest.remove(&call.line);
+                continue;
+            }
+            Err(e) => {
+                warn!(
+                    "skipping {} at {}:{}: {e:#}",
+                    call.name,
+                    path.display(),
+                    call.line,
+                );
+                continue;
+            }
+        };
+        if latest.insert(call.line, new_rev.clone()).as_ref() == Some(&new_rev) {
+            continue;
+        }
+
+        match update_call(opts, &call, false) {
+            Ok(bump) if bump.edits.is_empty() => {}
+            Ok(bump) => calls.push(json!({
+                "file": path,
+                "line": call.line,
+                "fetcher": call.name,
+                "old": bump.old_rev,
+                "new": bump.new_rev,
+            })),
+            Err(e) => {
+                warn!(
+                    "skipping {} at {}:{}: {e:#}",
+                    call.name,
+                    path.display(),
+                    call.line,
+                );
+            }
+        }
+    }
+
+    Ok(calls)
+}
+
+fn find_files(
+    path: &Path,
+    recursive: bool,
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<PathBuf>> {
+    if !recursive {
+        if path.is_dir() {
+            bail!(
+                "{} is a directory, pass --recursive to scan it",
+                path.display(),
+            );
+        }
+        return Ok(vec![path.into()]);
+    }
+
+    let include = include
+        .iter()
+        .map(|glob| Pattern::new(glob).with_context(|| format!("invalid glob {glob}")))
+        .collect::<Result<Vec<_>>>()?;
+    let exclude = exclude
+        .iter()
+        .map(|glob| Pattern::new(glob).with_context(|| format!("invalid glob {glob}")))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut files = Vec::new();
+    find_nix_files(path, &mut files)?;
+    files.sort();
+
+    files.retain(|file| {
+        let relative = file.strip_prefix(path).unwrap_or(file);
+        (include.is_empty() || include.iter().any(|glob| glob.matches_path(relative)))
+            && !exclude.iter().any(|glob| glob.matches_path(relative))
+    });
+
+    Ok(files)
+}
+
+fn find_nix_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let path = entry.path();
+        // symlinked directories are skipped, they can point back to one of their parents
+        if entry.file_type()?.is_dir() {
+            find_nix_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "nix") {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn update_file(
+    out: &mut impl Write,
+    opts: &Opts,
+    path: &Path,
+    attrs: &[String],
+    dry_run: bool,
+    mut changes: Option<&mut Vec<Value>>,
+) -> Result<usize> {
+    let src =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+
+    let calls: Vec<_> = find_calls(&src)
+        .into_iter()
+        .filter(|call| attrs.is_empty() || attrs.iter().any(|attr| bound_to(&src, call, attr)))
+        .collect();
+    let count = calls.len();
+
+    let mut edits = Vec::new();
+    for call in calls {
+        match update_call(opts, &call, true) {
+            Ok(bump) => {
+                let summary = format!(
+                    "{}:{}: {} {}",
+                    path.display(),
+                    call.line,
+                    call.name,
+                    bump.summary(),
+                );
+                // keep stdout for the JSON
+                if let Some(changes) = &mut changes {
+                    info!("{summary}");
+                    if !bump.edits.is_empty() {
+                        changes.push(bump.commit_entry(path)?);
+                    }
+                } else {
+                    writeln!(out, "{summary}")?;
+                }
+                edits.extend(bump.edits);
+            }
+            Err(e) => {
+                warn!(
+                    "skipping {} at {}:{}: {e:#}",
+                    call.name,
+                    path.display(),
+                    call.line,
+                );
+            }
+        }
+    }
+
+    if edits.is_empty() {
+        return Ok(count);
+    }
+
+    let mut updated = src.clone();
+    edits.sort_by_key(|(span, _)| span.start);
+    // calls can share the version binding, but only when they bump it to the same version
+    for pair in edits.windows(2) {
+        let [(x, old), (y, new)] = pair else {
+            continue;
+        };
+        if x == y && old != new {
+            bail!(
+                "conflicting updates of {} at line {}: {old} and {new}",
+                &src[x.clone()],
+                src[.. x.start].matches('\n').count() + 1,
+            );
+        }
+    }
+    edits.dedup();
+    for (span, value) in edits.into_iter().rev() {
+        updated.replace_range(span, &value);
+    }
+
+    if dry_run {
+        write_diff(out, path, &src, &updated)?;
+        return Ok(count);
+    }
+
+    fs::write(path, updated).with_context(|| format!("failed to write {}", path.display()))?;
+    info!("updated {}", path.display());
+
+    Ok(count)
+}
+
+// the edits only replace values within a line, so comparing the lines pairwise is enough
+fn write_diff(out: &mut impl Write, path: &Path, old: &str, new: &str) -> Result<()> {
+    writeln!(out, "--- a/{}", path.display())?;
+    writeln!(out, "+++ b/{}", path.display())?;
+
+    for (i, (old, new)) in old.lines().zip(new.lines()).enumerate() {
+        if old != new {
+            writeln!(out, "@@ -{0},1 +{0},1 @@", i + 1)?;
+            writeln!(out, "-{old}")?;
+            writeln!(out, "+{new}")?;
+        }
+    }
+
+    Ok(())
+}
+
+// what can be read from a fetcher call without fetching anything
+struct Current<'a, 'b> {
+    url: gix_url::Url,
+    rev_key: &'static str,
+    rev: &'b Attr<'a>,
+    old_rev: String,
+    hash: &'b Attr<'a>,
+    version: Option<(&'b Attr<'a>, &'a str)>,
+    interpolated: Option<&'a str>,
+    template: Option<String>,
+    submodules: Option<bool>,
+    args: Vec<(String, String)>,
+    args_str: Vec<(String, String)>,
+}
+
+impl<'a, 'b> Current<'a, 'b> {
+    fn read(call: &'b Call<'a>) -> Result<Self> {
+        let url = match call.fetcher {
+            FetcherFunction::FetchCrate => {
+                if call.get("registryDl").is_some() {
+                    bail!("crates from alternative registries are not supported");
+                }
+                format!("https://crates.io/crates/{}", call.required("pname")?)
+            }
+            FetcherFunction::FetchPypi => {
+                format!("https://pypi.org/project/{}", call.required("pname")?)
+            }
+            FetcherFunction::FetchHex => {
+                format!("https://hex.pm/packages/{}", call.required("pkg")?)
+            }
+            FetcherFunction::FetchRepoProject => call.required("manifest")?.into(),
+            FetcherFunction::FetchFromGitiles
+            | FetcherFunction::Fetchbzr
+            | FetcherFunction::Fetchdarcs
+            | FetcherFunction::Fetchfossil
+            | FetcherFunction::Fetchgit
+            | FetcherFunction::Fetchhg
+            | FetcherFunction::Fetchsvn => call.required("url")?.into(),
+            FetcherFunction::BuiltinsFetchGit => {
+                bail!("builtins.fetchGit does not support hashes");
+            }
+            FetcherFunction::Fetchurl => bail!("fetchurl does not support revisions"),
+            _ => forge_url(&call.fetcher, |name| call.string(name))?
+                .with_context(|| format!("{} is not supported", call.name))?,
+        };
+
+        if call.get("tag").is_some() {
+            bail!("updating tags is not supported");
+        }
+        let rev_key = match call.fetcher {
+            FetcherFunction::FetchCrate
+            | FetcherFunction::FetchHex
+            | FetcherFunction::FetchPypi => "version",
+            _ => "rev",
+        };
+        let rev = call
+            .get(rev_key)
+            .with_context(|| format!("{rev_key} is not specified"))?;
+        let version = match call.get("version").or(call.version.as_ref()) {
+            Some(version) if rev_key == "rev" => version.string().map(|value| (version, value)),
+            _ => None,
+        };
+        // the revision is a tag interpolating the version, e.g. rev = "v${version}",
+        // or a literal tag containing it, which the version is then bumped alongside
+        let interpolated = match rev.string() {
+            None if rev_key == "rev" => version_template(rev.value),
+            _ => None,
+        };
+        let template = match interpolated {
+            Some(template) => Some(template.to_owned()),
+            None => rev
+                .string()
+                .zip(version)
+                .filter(|(rev, (_, version))| rev.matches(version).count() == 1)
+                .map(|(rev, (_, version))| rev.replacen(version, "${version}", 1)),
+        };
+        let old_rev = match (interpolated, rev.string()) {
+            (Some(template), _) => {
+                let (_, version) =
+                    version.context("version is not specified as a string literal")?;
+                template.replace("${version}", version)
+            }
+            (None, Some(rev)) => rev.into(),
+            (None, None) if rev.value.bytes().all(|c| c.is_ascii_digit()) => rev.value.into(),
+            (None, None) => bail!("{rev_key} is not a string literal"),
+        };
+        let hash = call
+            .get("hash")
+            .or_else(|| call.get("sha256"))
+            .context("hash is not specified")?;
+
+        let submodules = match call
+            .get("fetchSubmodules")
+            .or_else(|| call.get("fetchSubrepos"))
+        {
+            Some(Attr { value: "true", .. }) => Some(true),
+            Some(Attr { value: "false", .. }) => Some(false),
+            Some(attr) => bail!("cannot evaluate {} = {}", attr.name, attr.value),
+            None => None,
+        };
+
+        let mut args = Vec::new();
+        let mut args_str = Vec::new();
+        for attr in &call.attrs {
+            if KNOWN_ARGS.contains(&attr.name) {
+                continue;
+            }
+            if let Some(value) = attr.string() {
+                args_str.push((attr.name.into(), value.into()));
+            } else {
+                args.push((attr.name.into(), attr.value.into()));
+            }
+        }
+
+        Ok(Self {
+            url: url.as_str().try_into()?,
+            rev_key,
+            rev,
+            old_rev,
+            hash,
+            version,
+            interpolated,
+            template,
+            submodules,
+            args,
+            args_str,
+        })
+    }
+
+    // registries already resolve the latest version
+    fn follows_tags(&self, opts: &Opts) -> bool {
+        (opts.tag || self.interpolated.is_some()) && self.rev_key == "rev"
+    }
+}
+
+// the latest revision or tag without prefetching it, None when the call is already at it
+fn latest_call_rev(opts: &Opts, call: &Call) -> Result<Option<String>> {
+    let current = Current::read(call)?;
+    let new_rev = with_fetcher(
+        opts,
+        Some(call.fetcher.clone()),
+        &current.url,
+        |fetcher, url| {
+            if current.follows_tags(opts) {
+                latest_tag(opts, fetcher, url)
+            } else {
+                fetcher.latest_rev(url)
+            }
+        },
+    )?;
+
+    Ok((new_rev != current.old_rev).then_some(new_rev))
+}
+
+fn update_call(opts: &Opts, call: &Call, compare: bool) -> Result<Bump> {
+    let current = Current::read(call)?;
+    let follows_tags = current.follows_tags(opts);
+    let Current {
+        url,
+        rev_key,
+        rev,
+        old_rev,
+        hash,
+        version,
+        interpolated,
+        template,
+        submodules,
+        args,
+        args_str,
+    } = current;
+
+    with_fetcher(opts, Some(call.fetcher.clone()), &url, |fetcher, url| {
+        let new_rev = if follows_tags {
+            Some(latest_tag(opts, fetcher, url)?)
+        } else {
+            None
+        };
+
+        let json = fetcher.fetch_json(
+            url,
+            new_rev,
+            false,
+            !opts.no_cache,
+            submodules,
+            args,
+            args_str,
+            Vec::new(),
+            Vec::new(),
+            opts.nixpkgs.clone(),
+        )?;
+
+        let fetcher_args = &json["args"];
+        let new_rev = fetcher_args[rev_key]
+            .as_str()
+            .context("failed to find the latest revision")?;
+        let new_hash = fetcher_args["hash"]
+            .as_str()
+            .or_else(|| fetcher_args["sha256"].as_str())
+            .context("failed to find the hash")?;
+
+        let new_version = match &template {
+            Some(template) if interpolated.is_some() => Some(template_version(template, new_rev)?),
+            Some(template) => template_version(template, new_rev).ok(),
+            None => None,
+        };
+
+        if old_rev == new_rev && hash.string() == Some(new_hash) {
+            return Ok(Bump {
+                url: url.url.into(),
+                old_rev,
+                new_rev: new_rev.into(),
+                edits: Vec::new(),
+                changes: Vec::new(),
+                compare_url: None,
+                commits: None,
+            });
+        }
+
+        let new_rev_value = if rev.string().is_some() {
+            format!(r#""{}""#, nix_escape(new_rev))
+        } else {
+            new_rev.into()
+        };
+
+        let (compare_url, commits) = if compare && old_rev != new_rev {
+            fetcher.compare(url, &old_rev, new_rev)
+        } else {
+            (None, None)
+        };
+
+        // the version binding is not necessarily within the call, so it is not prefixed
+        let prefix = call.attr.map(|attr| format!("{attr}.")).unwrap_or_default();
+        let mut edits = vec![(hash.span.clone(), format!(r#""{new_hash}""#))];
+        let mut changes = Vec::new();
+        if interpolated.is_none() {
+            edits.push((rev.span.clone(), new_rev_value));
+            changes.push((format!("{prefix}{rev_key}"), new_rev.into()));
+        }
+        if let (Some((version, _)), Some(new_version)) = (version, new_version) {
+            edits.push((
+                version.span.clone(),
+                format!(r#""{}""#, nix_escape(new_version)),
+            ));
+            changes.push(("version".into(), new_version.into()));
+        }
+        changes.push((format!("{prefix}{}", hash.name), new_hash.into()));
+
+        Ok(Bump {
+            url: url.url.into(),
+            old_rev,
+            new_rev: new_rev.into(),
+            edits,
+            changes,
+            compare_url,
+            commits,
+        })
+    })
+}
+
+fn find_calls(src: &str) -> Vec<Call> {
+    let names: IndexMap<_, _> = FetcherFunction::value_variants()
+        .iter()
+        .filter(|fetcher| {
+            !matches!(
+                fetcher,
+                FetcherFunction::BuiltinsFetchGit | FetcherFunction::Fetchurl,
+            )
+        })
+        .filter_map(|fetcher| Some((fetcher.to_possible_value()?.get_name().to_owned(), fetcher)))
+        .collect();
+
+    let bytes = src.as_bytes();
+    let mut calls = Vec::new();
+    let mut scopes = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        // fetcher calls within comments and strings are not evaluated
+        if bytes[i] == b'#' || bytes[i ..].starts_with(b"/*") {
+            i = skip_trivia(src, i);
+            continue;
+        }
+        if bytes[i] == b'"' || bytes[i ..].starts_with(b"''") {
+            i = skip_string(src, i);
+            continue;
+        }
+
+        match bytes[i] {
+            b'{' => {
+                let before = src[.. i].trim_end();
+                let rec = before
+                    .strip_suffix("rec")
+                    .is_some_and(|before| !before.bytes().next_back().is_some_and(is_ident));
+                scopes.push(if rec { Scope::Rec(i) } else { Scope::Other });
+            }
+            b'(' | b'[' => scopes.push(Scope::Other),
+            b'}' | b')' | b']' => {
+                scopes.pop();
+            }
+            _ => {}
+        }
+
+        if !is_ident_start(bytes[i]) || (i > 0 && is_ident(bytes[i - 1])) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < bytes.len() && is_ident(bytes[i]) {
+            i += 1;
+        }
+        let name = &src[start .. i];
+        match name {
+            "let" => scopes.push(Scope::Let(i)),
+            "in" if matches!(scopes.last(), Some(Scope::Let(_))) => {
+                scopes.pop();
+            }
+            _ => {}
+        }
+        let Some(fetcher) = names.get(name) else {
+            continue;
+        };
+
+        let open = skip_trivia(src, i);
+        if bytes.get(open) != Some(&b'{') {
+            continue;
+        }
+        let Some((attrs, end)) = parse_attrs(src, open + 1) else {
+            continue;
+        };
+
+        calls.push(Call {
+            fetcher: (*fetcher).clone(),
+            name,
+            attr: assigned_attr(src, start),
+            start,
+            line: src[.. start].matches('\n').count() + 1,
+            attrs,
+            version: scope_version(src, &scopes),
+        });
+        i = end;
+    }
+
+    calls
+}
+
+// the template of a revision interpolating the version, e.g. v${version} from "v${version}"
+fn version_template(value: &str) -> Option<&str> {
+    if value == "version" {
+        return Some("${version}");
+    }
+
+    let template = value.strip_prefix('"')?.strip_suffix('"')?;
+    let (prefix, suffix) = template.split_once("${version}")?;
+    (!prefix.contains(['"', '\\', '$']) && !suffix.contains(['"', '\\', '$'])).then_some(template)
+}
+
+// the version binding in scope of the call, from a let expression or a recursive attribute set
+// around it, e.g. stdenv.mkDerivation rec { version = ...; src = fetchFromGitHub { ... }; }
+fn scope_version<'a>(src: &'a str, scopes: &[Scope]) -> Option<Attr<'a>> {
+    scopes.iter().rev().find_map(|scope| {
+        let attrs = match *scope {
+            Scope::Let(i) => parse_let(src, i)?,
+            Scope::Rec(i) => parse_attrs(src, i + 1)?.0,
+            Scope::Other => return None,
+        };
+        attrs.into_iter().find(|attr| attr.name == "version")
+    })
+}
+
+// returns the attribute path the expression starting at the offset is bound to, e.g. src
+fn assigned_attr(src: &str, start: usize) -> Option<&str> {
+    let before = src[.. start].trim_end().strip_suffix('=')?;
+    if before.ends_with(['=', '!', '<', '>']) {
+        return None;
+    }
+
+    let before = before.trim_end();
+    let attr_start = before
+        .rfind(|c: char| !c.is_ascii() || !(is_ident(c as u8) || c == '.'))
+        .map_or(0, |i| i + 1);
+    let attr = &before[attr_start ..];
+    (!attr.is_empty()).then_some(attr)
+}
+
+// whether the call is within the value of a binding of the attribute path
+fn bound_to(src: &str, call: &Call, attr: &str) -> bool {
+    src[.. call.start].match_indices(attr).any(|(i, _)| {
+        if src[.. i]
+            .bytes()
+            .next_back()
+            .is_some_and(|c| is_ident(c) || c == b'.')
+        {
+            return false;
+        }
+
+        // the binding can also be a longer path starting with the attribute, e.g. foo.src
+        let rest = &src[i + attr.len() ..];
+        let rest = if rest.starts_with('.') {
+            rest.trim_start_matches(|c: char| c.is_ascii() && (is_ident(c as u8) || c == '.'))
+        } else {
+            rest
+        };
+        let Some(value) = rest.trim_start().strip_prefix('=') else {
+            return false;
+        };
+        if value.starts_with('=') {
+            return false;
+        }
+
+        let value_start = src.len() - value.len();
+        value_start <= call.start
+            && skip_expr(value).is_some_and(|end| call.start < value_start + end)
+    })
+}
+
+pub fn parse_attrs(src: &str, i: usize) -> Option<(Vec<Attr>, usize)> {
+    parse_bindings(src, i, |rest| rest.starts_with('}').then_some(1))
+}
+
+// the bindings of a let expression, starting after let
+fn parse_let(src: &str, i: usize) -> Option<Vec<Attr>> {
+    let (attrs, _) = parse_bindings(src, i, |rest| {
+        let keyword = rest.strip_prefix("in")?;
+        (!keyword.starts_with(|c: char| c.is_ascii() && is_ident(c as u8))).then_some(2)
+    })?;
+    Some(attrs)
+}
+
+// returns the bindings and the offset after the token ending them, whose length is returned by end
+fn parse_bindings(
+    src: &str,
+    mut i: usize,
+    end: impl Fn(&str) -> Option<usize>,
+) -> Option<(Vec<Attr>, usize)> {
+    let mut attrs = Vec::new();
+    loop {
+        i = skip_trivia(src, i);
+        let rest = src.get(i ..)?;
+
+        if let Some(len) = end(rest) {
+            return Some((attrs, i + len));
+        }
+
+        let end = i + skip_expr(rest)?;
+        if rest.starts_with("inherit") && !rest[7 ..].starts_with(|c: char| is_ident(c as u8)) {
+            i = end + 1;
+            continue;
+        }
+
+        let (name, value) = src[i .. end].split_once('=')?;
+        let value_start = i + name.len() + 1;
+        let value_start = value_start + (value.len() - value.trim_start().len());
+        let value = value.trim();
+
+        attrs.push(Attr {
+            name: name.trim(),
+            value,
+            span: value_start .. value_start + value.len(),
+        });
+        i = end + 1;
+    }
+}
+
+// returns the offset of the semicolon ending the binding
+fn skip_expr(src: &str) -> Option<usize> {
+    let bytes = src.as_bytes();
+    let mut stack = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match stack.last().copied() {
+            Some(b'"') => match bytes[i] {
+                b'\\' => i += 1,
+                b'"' => {
+                    stack.pop();
+                }
+                b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                    stack.push(b'{');
+                    i += 1;
+                }
+                _ => {}
+            },
+            Some(b'\'') => {
+                if src[i ..].starts_with("'''") || src[i ..].starts_with("''$") {
+                    i += 2;
+                } else if src[i ..].starts_with("''\\") {
+                    i += 2;
+                } else if src[i ..].starts_with("''") {
+                    stack.pop();
+                    i += 1;
+                } else if src[i ..].starts_with("${") {
+                    stack.push(b'{');
+                    i += 1;
+                }
+            }
+            _ => match bytes[i] {
+                b'"' => stack.push(b'"'),
+                b'\'' if bytes.get(i + 1) == Some(&b'\'') => {
+                    stack.push(b'\'');
+                    i += 1;
+                }
+                b'#' | b'/' if src[i ..].starts_with('#') || src[i ..].starts_with("/*") => {
+                    i = skip_trivia(src, i);
+                    continue;
+                }
+                b'(' | b'[' | b'{' => stack.push(bytes[i]),
+                b')' | b']' | b'}' => {
+                    stack.pop()?;
+                }
+                b';' if stack.is_empty() => return Some(i),
+                _ => {}
+            },
+        }
+        i += 1;
+    }
+
+    None
+}
+
+// returns the offset after the string starting at the offset, including its interpolations
+fn skip_string(src: &str, mut i: usize) -> usize {
+    let indented = src[i ..].starts_with("''");
+    i += if indented { 2 } else { 1 };
+
+    while let Some(rest) = src.get(i ..).filter(|rest| !rest.is_empty()) {
+        if indented
+            && (rest.starts_with("'''") || rest.starts_with("''$") || rest.starts_with("''\\"))
+        {
+            i += 3;
+        } else if indented && rest.starts_with("''") {
+            return i + 2;
+        } else if !indented && rest.starts_with('"') {
+            return i + 1;
+        } else if !indented && rest.starts_with('\\') {
+            i += 1 + rest[1 ..].chars().next().map_or(0, char::len_utf8);
+        } else if rest.starts_with("$$") {
+            i += 2;
+        } else if rest.starts_with("${") {
+            i = skip_interpolation(src, i + 2);
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+
+    i
+}
+
+// returns the offset after the brace closing the interpolation
+fn skip_interpolation(src: &str, mut i: usize) -> usize {
+    let bytes = src.as_bytes();
+    let mut depth = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'"' || bytes[i ..].starts_with(b"''") {
+            i = skip_string(src, i);
+            continue;
+        }
+        if bytes[i] == b'#' || bytes[i ..].starts_with(b"/*") {
+            i = skip_trivia(src, i);
+            continue;
+        }
+
+        match bytes[i] {
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return i + 1,
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    i
+}
+
+pub fn skip_trivia(src: &str, mut i: usize) -> usize {
+    loop {
+        let Some(rest) = src.get(i ..) else {
+            return i;
+        };
+        let trimmed = rest.trim_start();
+        i += rest.len() - trimmed.len();
+
+        if trimmed.starts_with('#') {
+            i += trimmed.find('\n').unwrap_or(trimmed.len());
+        } else if let Some(comment) = trimmed.strip_prefix("/*") {
+            i += comment.find("*/").map_or(trimmed.len(), |end| end + 4);
+        } else {
+            return i;
+        }
+    }
+}
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+pub fn is_ident(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'\'')
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::Path,
+    process::Command,
+    thread::sleep,
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::{
+    audit::AuditedCommand, cli::Opts, fetcher::Fetcher, info, latest_tag, parse_url,
+    update::watch_calls, warn, with_fetcher,
+};
+
+pub fn watch(
+    out: &mut impl Write,
+    opts: &Opts,
+    target: &str,
+    interval: Duration,
+    exec: Option<&str>,
+) -> Result<()> {
+    let path = Path::new(target);
+    let mut latest: Option<String> = None;
+    let mut seen = HashSet::new();
+    // the latest revisions of the calls in the file, so they are only prefetched when they change
+    let mut latest_calls = HashMap::new();
+
+    loop {
+        let events = if path.is_file() {
+            watch_calls(opts, path, &mut latest_calls)
+        } else {
+            latest_rev(opts, target).map(|rev| {
+                let event = match &latest {
+                    Some(old) if *old != rev => Some(json!({
+                        "url": target,
+                        "old": old,
+                        "new": rev,
+                    })),
+                    Some(_) => None,
+                    None => {
+                        info!("{target} is at {rev}");
+                        None
+                    }
+                };
+                latest = Some(rev);
+                event.into_iter().collect()
+            })
+        };
+
+        match events {
+            Ok(events) => {
+                // outdated calls keep being reported until the file is updated
+                for event in events {
+                    if seen.insert(event.to_string()) {
+                        notify(out, exec, &event)?;
+                    }
+                }
+            }
+            Err(e) => warn!("failed to check {target}: {e:#}"),
+        }
+
+        sleep(interval);
+    }
+}
+
+pub fn latest_rev(opts: &Opts, url: &str) -> Result<String> {
+    let url = parse_url(opts, url)?;
+    with_fetcher(opts, opts.fetcher.clone(), &url, |fetcher, url| {
+        if opts.tag {
+            latest_tag(opts, fetcher, url)
+        } else {
+            fetcher.latest_rev(url)
+        }
+    })
+}
+
+fn notify(out: &mut impl Write, exec: Option<&str>, event: &Value) -> Result<()> {
+    let Some(exec) = exec else {
+        serde_json::to_writer(&mut *out, event)?;
+        writeln!(out)?;
+        out.flush()?;
+        return Ok(());
+    };
+
+    info!("$ {exec}");
+    match Command::new("sh")
+        .arg("-c")
+        .arg(exec)
+        .env("NURL_EVENT", event.to_string())
+        .audited_status()
+    {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("{exec} exited with {status}"),
+        Err(e) => warn!("failed to run {exec}: {e}"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,6 @@
+{
+  nurl = {
+    url = "https://github.com/nix-community/nurl";
+    rev = "7d7ed2f239f2c77e26a724d7c786e8079407c767";
+  };
+}
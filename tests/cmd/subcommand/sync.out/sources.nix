@@ -0,0 +1,11 @@
+# generated by nurl sync from nurl.lock, do not edit
+{ pkgs ? import <nixpkgs> { } }:
+
+{
+  nurl = pkgs.fetchFromGitHub {
+    hash = "sha256-TtH0sfWFWe3oYK/8jJslqjrEY5rR7HGAVDD5iQ2+spY=";
+    owner = "nix-community";
+    repo = "nurl";
+    rev = "7d7ed2f239f2c77e26a724d7c786e8079407c767";
+  };
+}
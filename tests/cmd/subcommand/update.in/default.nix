@@ -0,0 +1,18 @@
+{ lib, rustPlatform, fetchFromGitHub }:
+
+rustPlatform.buildRustPackage rec {
+  pname = "nurl";
+  version = "0.3.0";
+
+  # src = fetchFromGitHub { owner = "nix-community"; repo = "nurl"; rev = "v${version}"; hash = ""; };
+  src = fetchFromGitHub {
+    owner = "nix-community";
+    repo = pname;
+    rev = "v${version}";
+    hash = "sha256-jZ+cCp1THDhfHH5yMmRPjGuthOqsgcF/3OjZ61FMdA4=";
+  };
+
+  meta = {
+    description = "fetchFromGitHub { owner = \"nix-community\"; repo = \"nurl\"; }";
+  };
+}
@@ -2,13 +2,29 @@ use std::fs;
 
 use assert_cmd::Command;
 use nu_glob::glob;
+use nurl::NurlRequest;
 use trycmd::TestCases;
 
 #[test]
 fn integration() {
     TestCases::new()
         .default_bin_name("nurl")
-        .case("tests/cmd/**/*.toml");
+        .case("tests/cmd/**/*.toml")
+        // files that the cases run on, e.g. config.toml
+        .skip("tests/cmd/**/*.in/**/*.toml");
+}
+
+#[test]
+fn api_parse() {
+    let mut request = NurlRequest::new("https://github.com/nix-community/patsh");
+    request.rev = Some("v0.2.0".into());
+    let output = request.parse().unwrap();
+
+    assert_eq!(output.fetcher, "fetchFromGitHub");
+    assert_eq!(output.args["owner"], "nix-community");
+    assert_eq!(output.args["repo"], "patsh");
+    assert_eq!(output.args["rev"], "v0.2.0");
+    assert_eq!(output.hash(), None);
 }
 
 #[test]
@@ -23,16 +39,33 @@ fn verify_outputs() {
             .strip_suffix(".stdout")
             .unwrap();
 
-        if matches!(name, "expr" | "hash" | "json" | "parse") {
+        // --parse only outputs the arguments, not an expression that can be built,
+        // --dry-run outputs what would be prefetched,
+        // and the subcommands do not output fetcher calls
+        let dir = path.parent().unwrap();
+        if dir.ends_with("parse")
+            || dir.ends_with("dry_run")
+            || dir.ends_with("subcommand")
+            || matches!(name, "batch" | "expr" | "flake" | "hash" | "json" | "parse")
+        {
             eprintln!("skipping {}", path.display());
             continue;
         }
 
-        eprintln!("testing {}", path.display());
-
         let mut expr = String::from_utf8(fs::read(&path).unwrap()).unwrap();
 
-        if name != "builtin_git" {
+        // outputs matched with wildcards, e.g. the revs of branches, cannot be built
+        if expr.contains("[..]") {
+            eprintln!("skipping {}", path.display());
+            continue;
+        }
+
+        eprintln!("testing {}", path.display());
+
+        if name == "rev_template" {
+            // the fetcher call is the src of an attribute set that also contains the version
+            expr = format!("with import <nixpkgs> {{ }}; ({expr}).src");
+        } else if name != "builtin_git" {
             expr.insert_str(0, "(import <nixpkgs> { }).");
 
             if name == "overwrite" {